@@ -1,7 +1,9 @@
 use std::{cell::RefCell, rc::Rc};
 
 use benthic::{
-    execution_policy::ExecutionPolicy,
+    clock::SystemClock,
+    error::EngineError,
+    execution_policy::{ExecuteAllways, ExecutionPolicy},
     margin::{MarginLotEventHandlerNull, MarginManager},
     market_data_policy::MarketDataNull,
     order::{Asset, LimitOrder, Market, Order, OrderType, Side},
@@ -11,11 +13,14 @@ use benthic::{
 use chrono::Utc;
 use criterion::{criterion_group, criterion_main, Criterion};
 use itertools::Itertools;
-use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
 
 const NUM_TRADERS: usize = 1_000;
 const NUM_ORDERS: usize = 500_000;
-const BENCHMARK_VERSION: &str = "Static Lots Handler (VecDeque)";
+const BENCHMARK_VERSION: &str =
+    "Static Lots Handler, Price Levels (intrusive LinkedList with HashMap index)";
+const NUM_CANCEL_ORDERS: usize = 100_000;
+const NUM_CANCEL_LEVELS: u64 = 20;
 
 struct BenchExecutions<T>
 where
@@ -24,6 +29,7 @@ where
     policy: T,
     pub placed_order_count: RefCell<usize>,
     pub executed_order_count: RefCell<usize>,
+    pub cancelled_order_count: RefCell<usize>,
 }
 
 impl<T> BenchExecutions<T>
@@ -35,6 +41,7 @@ where
             policy,
             placed_order_count: RefCell::new(0),
             executed_order_count: RefCell::new(0),
+            cancelled_order_count: RefCell::new(0),
         }
     }
 }
@@ -46,14 +53,15 @@ where
     fn place_order(
         &self,
         order_quantity: &mut benthic::order_book::OrderQuantity,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), EngineError> {
         *self.placed_order_count.borrow_mut() += 1;
         self.policy.place_order(order_quantity)
     }
     fn cancel_order(
         &self,
         order_quantity: &mut benthic::order_book::OrderQuantity,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), EngineError> {
+        *self.cancelled_order_count.borrow_mut() += 1;
         self.policy.cancel_order(order_quantity)
     }
     fn execute_orders(
@@ -61,11 +69,26 @@ where
         executed_quantity: &mut u64,
         aggressor_order: &mut benthic::order_book::OrderQuantity,
         book_order: &mut benthic::order_book::OrderQuantity,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), EngineError> {
         *self.executed_order_count.borrow_mut() += 1;
         self.policy
             .execute_orders(executed_quantity, aggressor_order, book_order)
     }
+    fn check_executions(
+        &self,
+        aggressor_order: &benthic::order_book::OrderQuantity,
+        executions: &[(u64, benthic::order_book::OrderQuantity)],
+    ) -> Result<(), EngineError> {
+        self.policy.check_executions(aggressor_order, executions)
+    }
+    fn check_replace_order(
+        &self,
+        replaced_order: &benthic::order_book::OrderQuantity,
+        order_quantity: &benthic::order_book::OrderQuantity,
+    ) -> Result<(), EngineError> {
+        self.policy
+            .check_replace_order(replaced_order, order_quantity)
+    }
 }
 
 fn benchmark_order_placement(c: &mut Criterion) {
@@ -92,6 +115,8 @@ fn benchmark_order_placement(c: &mut Criterion) {
         multiplier: 1,
         quote_decimals: 2,
         base_decimals: 5,
+        price_protection: None,
+        order_limits: Default::default(),
     });
 
     let market_eth_usdt = Rc::new(Market {
@@ -102,6 +127,8 @@ fn benchmark_order_placement(c: &mut Criterion) {
         multiplier: 1,
         quote_decimals: 2,
         base_decimals: 5,
+        price_protection: None,
+        order_limits: Default::default(),
     });
 
     let market_btc_eth = Rc::new(Market {
@@ -112,6 +139,8 @@ fn benchmark_order_placement(c: &mut Criterion) {
         multiplier: 1,
         quote_decimals: 4,
         base_decimals: 5,
+        price_protection: None,
+        order_limits: Default::default(),
     });
 
     let order_books = Rc::new(OrderBooks::new(&[
@@ -119,7 +148,7 @@ fn benchmark_order_placement(c: &mut Criterion) {
         Rc::new(RefCell::new(OrderBook::new(market_btc_eth.clone()))),
     ]));
 
-    let mut order_manager = OrderManager::new(order_books);
+    let mut order_manager = OrderManager::new(order_books, Rc::new(SystemClock));
 
     let mut margin_manager = MarginManager::new(MarginLotEventHandlerNull);
 
@@ -141,6 +170,11 @@ fn benchmark_order_placement(c: &mut Criterion) {
                     },
                     participant_id: n,
                     order_id: n,
+                    client_order_id: None,
+                    expire_time: None,
+                    self_trade_prevention: None,
+                    cancel_on_disconnect: false,
+                    reduce_only: false,
                     order_data: OrderType::Deposit(rng.random_range(1_00000..100_00000)),
                 }),
                 rng.random_range(400000..10000000),
@@ -154,6 +188,11 @@ fn benchmark_order_placement(c: &mut Criterion) {
                 market: market_btc_eth.clone(),
                 order_id: NUM_TRADERS + n,
                 participant_id: rng.random_range(0..NUM_TRADERS),
+                client_order_id: None,
+                expire_time: None,
+                self_trade_prevention: None,
+                cancel_on_disconnect: false,
+                reduce_only: false,
                 order_data: OrderType::Limit(LimitOrder {
                     side: if rng.random_bool(0.5) {
                         Side::Bid
@@ -213,5 +252,110 @@ fn benchmark_order_placement(c: &mut Criterion) {
     );
 }
 
-criterion_group!(benches, benchmark_order_placement);
+fn benchmark_order_cancellation(c: &mut Criterion) {
+    let asset_usdt = Rc::new(Asset {
+        symbol: "USDT".into(),
+        decimals: 2,
+    });
+
+    let asset_btc = Rc::new(Asset {
+        symbol: "BTC".into(),
+        decimals: 7,
+    });
+
+    let market_btc_usdt = Rc::new(Market {
+        symbol: "BTC/USDT".into(),
+        base_asset: asset_btc.clone(),
+        quote_asset: asset_usdt.clone(),
+        tick: 1,
+        multiplier: 1,
+        quote_decimals: 2,
+        base_decimals: 5,
+        price_protection: None,
+        order_limits: Default::default(),
+    });
+
+    let order_books = Rc::new(OrderBooks::new(&[Rc::new(RefCell::new(OrderBook::new(
+        market_btc_usdt.clone(),
+    )))]));
+
+    let mut order_manager = OrderManager::new(order_books, Rc::new(SystemClock));
+
+    let mut rng = SmallRng::seed_from_u64(123456999);
+
+    // Bids and asks never cross, so that every order rests on one of few hot levels
+    let orders = (0..NUM_CANCEL_ORDERS)
+        .map(|n| {
+            let side = if rng.random_bool(0.5) {
+                Side::Bid
+            } else {
+                Side::Ask
+            };
+            let level = rng.random_range(0..NUM_CANCEL_LEVELS);
+            Rc::new(Order {
+                market: market_btc_usdt.clone(),
+                order_id: n,
+                participant_id: rng.random_range(0..NUM_TRADERS),
+                client_order_id: None,
+                expire_time: None,
+                self_trade_prevention: None,
+                cancel_on_disconnect: false,
+                reduce_only: false,
+                order_data: OrderType::Limit(LimitOrder {
+                    side,
+                    price: match side {
+                        Side::Bid => 5000000 - level,
+                        Side::Ask => 5000100 + level,
+                    },
+                    quantity: rng.random_range(1_00000..100_00000),
+                }),
+            })
+        })
+        .collect_vec();
+
+    let mut cancels = orders
+        .iter()
+        .map(|order| (order.participant_id, order.order_id))
+        .collect_vec();
+    cancels.shuffle(&mut rng);
+
+    let execution_policy = BenchExecutions::new(ExecuteAllways);
+    let market_data_policy = MarketDataNull {};
+
+    println!(
+        "Config: NUM_CANCEL_ORDERS = {NUM_CANCEL_ORDERS}, NUM_CANCEL_LEVELS = {NUM_CANCEL_LEVELS}"
+    );
+
+    c.bench_function("order_cancellation_hot_levels", |b| {
+        b.iter(|| {
+            for order in &orders {
+                let _ = order_manager.place_order(
+                    order.clone(),
+                    &execution_policy,
+                    &market_data_policy,
+                );
+            }
+            for (participant_id, order_id) in &cancels {
+                let _ = order_manager.cancel_order(
+                    *participant_id,
+                    *order_id,
+                    &execution_policy,
+                    &market_data_policy,
+                );
+            }
+        });
+    });
+
+    println!(
+        "Finished: orders {}, cancels {}",
+        execution_policy.placed_order_count.borrow(),
+        execution_policy.cancelled_order_count.borrow()
+    );
+}
+
+criterion_group!(
+    benches,
+    benchmark_order_placement,
+    benchmark_order_cancellation
+);
 criterion_main!(benches);
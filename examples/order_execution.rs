@@ -3,6 +3,7 @@ use std::{cell::RefCell, rc::Rc};
 use itertools::Itertools;
 
 use benthic::{
+    clock::SystemClock,
     margin::{MarginLotEventHandlerNull, MarginManager},
     market_data_policy::MarketDataNull,
     order::{price_fmt, Asset, LimitOrder, Market, Order, OrderType, Side},
@@ -34,6 +35,8 @@ fn main() {
         multiplier: 1,
         quote_decimals: 2,
         base_decimals: 5,
+        price_protection: None,
+        order_limits: Default::default(),
     });
 
     let market_eth_usdt = Rc::new(Market {
@@ -44,6 +47,8 @@ fn main() {
         multiplier: 1,
         quote_decimals: 2,
         base_decimals: 5,
+        price_protection: None,
+        order_limits: Default::default(),
     });
 
     let market_btc_eth = Rc::new(Market {
@@ -54,6 +59,8 @@ fn main() {
         multiplier: 1,
         quote_decimals: 4,
         base_decimals: 5,
+        price_protection: None,
+        order_limits: Default::default(),
     });
 
     let order_books = Rc::new(OrderBooks::new(&[
@@ -64,7 +71,7 @@ fn main() {
     let trader_a = 1001;
     let trader_b = 1002;
 
-    let mut order_manager = OrderManager::new(order_books);
+    let mut order_manager = OrderManager::new(order_books, Rc::new(SystemClock));
     let mut margin_manager = MarginManager::new(LogMarginLots::new(MarginLotEventHandlerNull));
     println!("Margin  -->  create Account({})", trader_a);
     margin_manager
@@ -78,6 +85,11 @@ fn main() {
                 market: market_btc_usdt.clone(),
                 participant_id: trader_a,
                 order_id: 101,
+                client_order_id: None,
+                expire_time: None,
+                self_trade_prevention: None,
+                cancel_on_disconnect: false,
+                reduce_only: false,
                 order_data: OrderType::Deposit(200000),
             }),
             5000000,
@@ -95,6 +107,11 @@ fn main() {
                 market: market_eth_usdt.clone(),
                 participant_id: trader_b,
                 order_id: 102,
+                client_order_id: None,
+                expire_time: None,
+                self_trade_prevention: None,
+                cancel_on_disconnect: false,
+                reduce_only: false,
                 order_data: OrderType::Deposit(2000000),
             }),
             400000,
@@ -109,6 +126,11 @@ fn main() {
             market: market_btc_usdt.clone(),
             order_id: 1,
             participant_id: trader_a,
+            client_order_id: None,
+            expire_time: None,
+            self_trade_prevention: None,
+            cancel_on_disconnect: false,
+            reduce_only: false,
             order_data: OrderType::Limit(LimitOrder {
                 side: Side::Bid,
                 price: 5000000,
@@ -119,6 +141,11 @@ fn main() {
             market: market_btc_eth.clone(),
             order_id: 2,
             participant_id: trader_a,
+            client_order_id: None,
+            expire_time: None,
+            self_trade_prevention: None,
+            cancel_on_disconnect: false,
+            reduce_only: false,
             order_data: OrderType::Limit(LimitOrder {
                 side: Side::Ask,
                 price: 125000,
@@ -129,6 +156,11 @@ fn main() {
             market: market_btc_eth.clone(),
             order_id: 3,
             participant_id: trader_b,
+            client_order_id: None,
+            expire_time: None,
+            self_trade_prevention: None,
+            cancel_on_disconnect: false,
+            reduce_only: false,
             order_data: OrderType::Limit(LimitOrder {
                 side: Side::Bid,
                 price: 125000,
@@ -139,6 +171,11 @@ fn main() {
             market: market_btc_eth.clone(),
             order_id: 4,
             participant_id: trader_b,
+            client_order_id: None,
+            expire_time: None,
+            self_trade_prevention: None,
+            cancel_on_disconnect: false,
+            reduce_only: false,
             order_data: OrderType::Limit(LimitOrder {
                 side: Side::Bid,
                 price: 120000,
@@ -149,6 +186,11 @@ fn main() {
             market: market_btc_eth.clone(),
             order_id: 5,
             participant_id: trader_b,
+            client_order_id: None,
+            expire_time: None,
+            self_trade_prevention: None,
+            cancel_on_disconnect: false,
+            reduce_only: false,
             order_data: OrderType::Limit(LimitOrder {
                 side: Side::Bid,
                 price: 140000,
@@ -159,6 +201,11 @@ fn main() {
             market: market_btc_eth.clone(),
             order_id: 6,
             participant_id: trader_b,
+            client_order_id: None,
+            expire_time: None,
+            self_trade_prevention: None,
+            cancel_on_disconnect: false,
+            reduce_only: false,
             order_data: OrderType::Limit(LimitOrder {
                 side: Side::Bid,
                 price: 150000,
@@ -181,58 +228,59 @@ fn main() {
         }
     };
 
-    let print_portfolio = |margin_manager: &MarginManager<LogMarginLots<MarginLotEventHandlerNull>>| {
-        println!("");
-        for (account_id, account) in margin_manager
-            .get_participants()
-            .iter()
-            .collect_vec()
-            .into_iter()
-            .sorted_by_key(|x| x.0)
-        {
-            println!(
-                "Account {: >5}   {: >12} {: >10} | {: >10} {: >12}",
-                account_id, "(Open)", "Short", "Long", "(Open)",
-            );
-            println!("----------------------------------------------------------------");
-
-            for (symbol, asset_data) in account
-                .borrow()
-                .portfolio
+    let print_portfolio =
+        |margin_manager: &MarginManager<LogMarginLots<MarginLotEventHandlerNull>>| {
+            println!();
+            for (account_id, account) in margin_manager
+                .get_participants()
                 .iter()
                 .collect_vec()
                 .into_iter()
                 .sorted_by_key(|x| x.0)
             {
-                let asset_data = asset_data.borrow();
                 println!(
-                    "\t{: >5}   {: >12} {: >10} | {: >10} {: >12}",
-                    symbol,
-                    format!(
-                        "({})",
+                    "Account {: >5}   {: >12} {: >10} | {: >10} {: >12}",
+                    account_id, "(Open)", "Short", "Long", "(Open)",
+                );
+                println!("----------------------------------------------------------------");
+
+                for (symbol, asset_data) in account
+                    .borrow()
+                    .portfolio
+                    .iter()
+                    .collect_vec()
+                    .into_iter()
+                    .sorted_by_key(|x| x.0)
+                {
+                    let asset_data = asset_data.borrow();
+                    println!(
+                        "\t{: >5}   {: >12} {: >10} | {: >10} {: >12}",
+                        symbol,
+                        format!(
+                            "({})",
+                            price_fmt(
+                                asset_data.delivered.quantity_open,
+                                asset_data.asset.decimals
+                            )
+                        ),
                         price_fmt(
-                            asset_data.delivered.quantity_open,
+                            asset_data.delivered.quantity_committed,
                             asset_data.asset.decimals
-                        )
-                    ),
-                    price_fmt(
-                        asset_data.delivered.quantity_committed,
-                        asset_data.asset.decimals
-                    ),
-                    price_fmt(
-                        asset_data.received.quantity_committed,
-                        asset_data.asset.decimals
-                    ),
-                    format!(
-                        "({})",
-                        price_fmt(asset_data.received.quantity_open, asset_data.asset.decimals)
-                    ),
-                );
-            }
+                        ),
+                        price_fmt(
+                            asset_data.received.quantity_committed,
+                            asset_data.asset.decimals
+                        ),
+                        format!(
+                            "({})",
+                            price_fmt(asset_data.received.quantity_open, asset_data.asset.decimals)
+                        ),
+                    );
+                }
 
-            println!("");
-        }
-    };
+                println!();
+            }
+        };
 
     print_portfolio(execution_policy.inner());
 
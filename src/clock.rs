@@ -0,0 +1,47 @@
+use std::{
+    cell::Cell,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Source of engine time in milliseconds since Unix epoch
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis() as u64)
+    }
+}
+
+/// Clock that moves only when told, e.g. for deterministic tests or replay
+#[derive(Default)]
+pub struct ManualClock {
+    time: Cell<u64>,
+}
+
+impl ManualClock {
+    pub fn new(time: u64) -> Self {
+        Self {
+            time: Cell::new(time),
+        }
+    }
+
+    pub fn set_time(&self, time: u64) {
+        self.time.set(time);
+    }
+
+    pub fn advance(&self, duration: u64) {
+        self.time.set(self.time.get() + duration);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.time.get()
+    }
+}
@@ -0,0 +1,191 @@
+use std::{error::Error, fmt};
+
+use crate::order::{Order, OrderRejectReason};
+
+/// Error of the engine, with participant and order it relates to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineError {
+    pub participant_id: usize,
+    pub order_id: usize,
+    pub kind: EngineErrorKind,
+}
+
+impl EngineError {
+    pub fn new(order: &Order, kind: impl Into<EngineErrorKind>) -> Self {
+        Self::with_ids(order.participant_id, order.order_id, kind)
+    }
+
+    pub fn with_ids(
+        participant_id: usize,
+        order_id: usize,
+        kind: impl Into<EngineErrorKind>,
+    ) -> Self {
+        Self {
+            participant_id,
+            order_id,
+            kind: kind.into(),
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (order {}:{})",
+            self.kind, self.participant_id, self.order_id
+        )
+    }
+}
+
+impl Error for EngineError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineErrorKind {
+    /// Order does not meet the rules of its market
+    Validation(OrderRejectReason),
+    /// Margin accounting of order failed
+    Margin(MarginError),
+    /// Order cannot be placed, matched, or changed on the book
+    Book(BookError),
+    /// Quantity or value calculation overflowed
+    Overflow,
+    /// Something order refers to does not exist
+    NotFound(NotFoundError),
+}
+
+impl fmt::Display for EngineErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Validation(reason) => write!(f, "{}", reason),
+            Self::Margin(error) => write!(f, "{}", error),
+            Self::Book(error) => write!(f, "{}", error),
+            Self::Overflow => write!(f, "Mathematical overflow"),
+            Self::NotFound(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<OrderRejectReason> for EngineErrorKind {
+    fn from(reason: OrderRejectReason) -> Self {
+        Self::Validation(reason)
+    }
+}
+
+impl From<MarginError> for EngineErrorKind {
+    fn from(error: MarginError) -> Self {
+        Self::Margin(error)
+    }
+}
+
+impl From<BookError> for EngineErrorKind {
+    fn from(error: BookError) -> Self {
+        Self::Book(error)
+    }
+}
+
+impl From<NotFoundError> for EngineErrorKind {
+    fn from(error: NotFoundError) -> Self {
+        Self::NotFound(error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MarginError {
+    /// Participant has no margin account
+    AccountNotFound,
+    /// Margin account has no account for the asset
+    AssetAccountNotFound(String),
+    InvalidTransferType,
+    /// Asset account cannot deliver quantity, with its balance and buying power
+    InsufficientBalance(String),
+    /// Initial margin required after trade would exceed equity of account
+    InsufficientMargin,
+    /// Asset cannot be valued without its mark price
+    MarkPriceNotFound(String),
+    /// Reduce-only order would increase position, or turn it to the other side
+    PositionNotReduced,
+    ExecutionBeginFailed,
+    ExecutionCommitFailed,
+}
+
+impl fmt::Display for MarginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AccountNotFound => write!(f, "Margin not found"),
+            Self::AssetAccountNotFound(symbol) => write!(f, "Margin data not found for {}", symbol),
+            Self::InvalidTransferType => write!(f, "Invalid transfer type"),
+            Self::InsufficientBalance(symbol) => write!(f, "Insufficient balance of {}", symbol),
+            Self::InsufficientMargin => write!(f, "Insufficient margin"),
+            Self::MarkPriceNotFound(symbol) => write!(f, "Mark price not found for {}", symbol),
+            Self::PositionNotReduced => write!(f, "Reduce-only order would not reduce position"),
+            Self::ExecutionBeginFailed => write!(f, "Margin failed begin execute"),
+            Self::ExecutionCommitFailed => write!(f, "Margin failed commit execute"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BookError {
+    /// Order has no quantity to place, cancel, or execute
+    NotEnoughQuantity,
+    /// Order type is not supported by the operation
+    InvalidOrderType,
+    OrderExpired,
+    DuplicateOrder,
+    DuplicateClientOrderId,
+    /// Order id is in the range of exchange-assigned ids, but was not assigned
+    UnassignedOrderId,
+    /// Orders cannot form One-Cancels-Other group
+    InvalidOcoGroup,
+    PostOnlyWouldCross,
+    PostOnlyCannotReprice,
+    FillOrKillNotFilled,
+    /// Book index and price levels disagree
+    Inconsistent,
+}
+
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let error = match self {
+            Self::NotEnoughQuantity => "Not enough quantity",
+            Self::InvalidOrderType => "Invalid order type",
+            Self::OrderExpired => "Order already expired",
+            Self::DuplicateOrder => "Order already exists",
+            Self::DuplicateClientOrderId => "Client order id already used",
+            Self::UnassignedOrderId => "Order id was not assigned by exchange",
+            Self::InvalidOcoGroup => "Invalid orders for OCO",
+            Self::PostOnlyWouldCross => "Post-only order would cross the book",
+            Self::PostOnlyCannotReprice => "Post-only order cannot be re-priced",
+            Self::FillOrKillNotFilled => "Not enough quantity to fill order",
+            Self::Inconsistent => "Book is inconsistent",
+        };
+        write!(f, "{}", error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotFoundError {
+    Order,
+    ClientOrderId(String),
+    /// Book for market symbol
+    Book(String),
+    MarginModel,
+}
+
+impl fmt::Display for NotFoundError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Order => write!(f, "Order not found"),
+            Self::ClientOrderId(client_order_id) => {
+                write!(
+                    f,
+                    "Order not found for client order id: {}",
+                    client_order_id
+                )
+            }
+            Self::Book(symbol) => write!(f, "Book not found for symbol: {}", symbol),
+            Self::MarginModel => write!(f, "Margin model not found"),
+        }
+    }
+}
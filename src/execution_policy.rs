@@ -1,35 +1,62 @@
-use std::error::Error;
-
-use crate::order_book::OrderQuantity;
+use crate::{
+    error::{BookError, EngineError},
+    order_book::OrderQuantity,
+};
 
 pub trait ExecutionPolicy {
-    fn place_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), Box<dyn Error>>;
-    fn cancel_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), Box<dyn Error>>;
+    fn place_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), EngineError>;
+    fn cancel_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), EngineError>;
+    /// Execute orders against each other, or reject execution with error of the
+    /// order at fault, and without changing anything
     fn execute_orders(
         &self,
         executed_quantity: &mut u64,
         aggressor_order: &mut OrderQuantity,
         book_order: &mut OrderQuantity,
-    ) -> Result<(), Box<dyn Error>>;
+    ) -> Result<(), EngineError>;
+    /// Check that aggressor order could be executed against each of book
+    /// orders in turn, with given quantities, without changing anything
+    ///
+    /// Fill-or-Kill order is killed on error, before any of it is executed.
+    fn check_executions(
+        &self,
+        aggressor_order: &OrderQuantity,
+        executions: &[(u64, OrderQuantity)],
+    ) -> Result<(), EngineError>;
+    /// Check that order could be placed in place of replaced book order, whose
+    /// promise would be cancelled first, without changing anything
+    ///
+    /// Amended order is checked before it is taken off the book.
+    fn check_replace_order(
+        &self,
+        replaced_order: &OrderQuantity,
+        order_quantity: &OrderQuantity,
+    ) -> Result<(), EngineError>;
 }
 
 pub struct ExecuteAllways;
 
 impl ExecutionPolicy for ExecuteAllways {
-    fn place_order(&self, book_order: &mut OrderQuantity) -> Result<(), Box<dyn Error>> {
+    fn place_order(&self, book_order: &mut OrderQuantity) -> Result<(), EngineError> {
         // TODO: Check available balance/margine for participant
-        if book_order.quantity > 0 {
+        if book_order.get_total_quantity() > 0 {
             Ok(())
         } else {
-            Err("Not enough quantity".into())
+            Err(EngineError::new(
+                &book_order.order,
+                BookError::NotEnoughQuantity,
+            ))
         }
     }
 
-    fn cancel_order(&self, book_order: &mut OrderQuantity) -> Result<(), Box<dyn Error>> {
-        if book_order.quantity > 0 {
+    fn cancel_order(&self, book_order: &mut OrderQuantity) -> Result<(), EngineError> {
+        if book_order.get_total_quantity() > 0 {
             Ok(())
         } else {
-            Err("Not enough quantity".into())
+            Err(EngineError::new(
+                &book_order.order,
+                BookError::NotEnoughQuantity,
+            ))
         }
     }
 
@@ -38,14 +65,40 @@ impl ExecutionPolicy for ExecuteAllways {
         executed_quantity: &mut u64,
         aggressor_order: &mut OrderQuantity,
         book_order: &mut OrderQuantity,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), EngineError> {
         // TODO: Check available balance/margine for each participant
         if *executed_quantity > 0 {
             aggressor_order.quantity -= *executed_quantity;
-            book_order.quantity += *executed_quantity;
+            book_order.quantity -= *executed_quantity;
+            Ok(())
+        } else {
+            Err(EngineError::new(
+                &aggressor_order.order,
+                BookError::NotEnoughQuantity,
+            ))
+        }
+    }
+
+    fn check_executions(
+        &self,
+        _aggressor_order: &OrderQuantity,
+        _executions: &[(u64, OrderQuantity)],
+    ) -> Result<(), EngineError> {
+        Ok(())
+    }
+
+    fn check_replace_order(
+        &self,
+        _replaced_order: &OrderQuantity,
+        order_quantity: &OrderQuantity,
+    ) -> Result<(), EngineError> {
+        if order_quantity.get_total_quantity() > 0 {
             Ok(())
         } else {
-            Err("Not enough quantity".into())
+            Err(EngineError::new(
+                &order_quantity.order,
+                BookError::NotEnoughQuantity,
+            ))
         }
     }
 }
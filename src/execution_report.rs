@@ -0,0 +1,83 @@
+use crate::{
+    error::EngineErrorKind,
+    order_state::{OrderState, OrderStatus},
+};
+
+/// What happened to order, in the style of FIX ExecType
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecType {
+    New,
+    Trade,
+    Cancelled,
+    /// Order was amended by participant
+    Replaced,
+    /// Open quantity was reduced without participant asking for it, e.g. by
+    /// self-trade prevention
+    Restated,
+    Rejected,
+    Expired,
+}
+
+/// Whether execution added liquidity to the book or took it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+/// Report of order state change, in the style of FIX ExecutionReport
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionReport {
+    /// Unique and increasing for reports of the same order manager
+    pub exec_id: u64,
+    pub participant_id: usize,
+    pub order_id: usize,
+    pub client_order_id: Option<String>,
+    pub exec_type: ExecType,
+    pub order_status: OrderStatus,
+    /// Quantity of this execution, if it is a trade
+    pub last_quantity: u64,
+    /// Price of this execution, if it is a trade
+    pub last_price: u64,
+    /// Cumulative executed quantity
+    pub filled_quantity: u64,
+    pub average_price: Option<u64>,
+    pub leaves_quantity: u64,
+    /// Side of the trade, if it is one
+    pub liquidity: Option<Liquidity>,
+    pub reject_reason: Option<EngineErrorKind>,
+    /// Milliseconds since Unix epoch
+    pub transact_time: u64,
+}
+
+impl ExecutionReport {
+    /// Report current state of order, without any trade
+    pub fn new(exec_id: u64, transact_time: u64, exec_type: ExecType, state: &OrderState) -> Self {
+        Self {
+            exec_id,
+            participant_id: state.order.participant_id,
+            order_id: state.order.order_id,
+            client_order_id: state.order.client_order_id.clone(),
+            exec_type,
+            order_status: state.status,
+            last_quantity: 0,
+            last_price: 0,
+            filled_quantity: state.filled_quantity,
+            average_price: state.get_average_price(),
+            leaves_quantity: state.leaves_quantity,
+            liquidity: None,
+            reject_reason: None,
+            transact_time,
+        }
+    }
+}
+
+pub trait ExecutionReportSink {
+    fn handle_execution_report(&self, report: &ExecutionReport);
+}
+
+pub struct ExecutionReportNull;
+
+impl ExecutionReportSink for ExecutionReportNull {
+    fn handle_execution_report(&self, _report: &ExecutionReport) {}
+}
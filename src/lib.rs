@@ -1,6 +1,11 @@
+pub mod clock;
+pub mod error;
 pub mod execution_policy;
+pub mod execution_report;
+pub mod liquidation;
 pub mod margin;
 pub mod market_data_policy;
 pub mod order;
 pub mod order_book;
 pub mod order_manager;
+pub mod order_state;
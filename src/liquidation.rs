@@ -0,0 +1,472 @@
+use std::rc::Rc;
+
+use crate::{
+    error::{EngineError, NotFoundError},
+    margin::{MarginLotEventHandler, MarginManager},
+    market_data_policy::MarketDataPolicy,
+    order::*,
+    order_book::OrderOutcome,
+    order_manager::{MassCancelFilter, MassCancelSummary, OrderManager},
+};
+
+/// Step of liquidating an account, whose equity fell below maintenance margin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LiquidationEvent {
+    /// Margin ratio of account fell below 10000 basis points, see
+    /// [`MarginTradingAccount::get_margin_ratio`](crate::margin::MarginTradingAccount::get_margin_ratio)
+    MarginCall {
+        participant_id: usize,
+        margin_ratio: i128,
+    },
+    /// Open orders of account were cancelled
+    OrdersCancelled {
+        participant_id: usize,
+        summary: MassCancelSummary,
+    },
+    /// Reduce-only order was submitted to close part of a position
+    OrderSubmitted {
+        participant_id: usize,
+        order_id: usize,
+        symbol: String,
+        side: Side,
+        quantity: u64,
+        outcome: Result<OrderOutcome, EngineError>,
+    },
+    /// Account is back above maintenance margin
+    Restored {
+        participant_id: usize,
+        margin_ratio: Option<i128>,
+    },
+    /// Account is still below maintenance margin, but nothing more could be
+    /// executed
+    Stalled {
+        participant_id: usize,
+        margin_ratio: i128,
+    },
+}
+
+pub trait LiquidationEventHandler {
+    fn handle_liquidation_event(&self, event: &LiquidationEvent);
+}
+
+pub struct LiquidationEventNull;
+
+impl LiquidationEventHandler for LiquidationEventNull {
+    fn handle_liquidation_event(&self, _event: &LiquidationEvent) {}
+}
+
+/// Liquidates accounts, whose maintenance margin is breached after mark price
+/// update, by market orders reducing their positions
+pub struct LiquidationEngine {
+    /// Markets, in which positions in their base assets are liquidated
+    markets: Vec<Rc<Market>>,
+    /// Part of position closed by one liquidation order, in basis points
+    step_rate: u64,
+    event_handler: Rc<dyn LiquidationEventHandler>,
+}
+
+impl LiquidationEngine {
+    pub fn new(markets: &[Rc<Market>]) -> Self {
+        Self {
+            markets: markets.to_vec(),
+            step_rate: 2500,
+            event_handler: Rc::new(LiquidationEventNull),
+        }
+    }
+
+    /// Set part of position closed by one liquidation order, in basis points
+    pub fn set_step_rate(&mut self, step_rate: u64) {
+        self.step_rate = step_rate.clamp(1, 10000);
+    }
+
+    pub fn set_event_handler(&mut self, event_handler: Rc<dyn LiquidationEventHandler>) {
+        self.event_handler = event_handler;
+    }
+
+    /// Update mark price in margin model, and liquidate accounts breaching
+    /// maintenance margin at new price
+    ///
+    /// Returns participants, whose accounts were liquidated.
+    pub fn update_mark_price<TLotHandler>(
+        &self,
+        symbol: &str,
+        price: u64,
+        order_manager: &mut OrderManager,
+        margin_manager: &mut MarginManager<TLotHandler>,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<Vec<usize>, EngineError>
+    where
+        TLotHandler: MarginLotEventHandler + Clone,
+    {
+        margin_manager
+            .get_margin_model_mut()
+            .ok_or_else(|| EngineError::with_ids(0, 0, NotFoundError::MarginModel))?
+            .set_mark_price(symbol, price);
+        self.liquidate_accounts(order_manager, margin_manager, market_data_policy)
+    }
+
+    /// Liquidate all accounts breaching maintenance margin, in order of
+    /// participant id
+    pub fn liquidate_accounts<TLotHandler>(
+        &self,
+        order_manager: &mut OrderManager,
+        margin_manager: &MarginManager<TLotHandler>,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<Vec<usize>, EngineError>
+    where
+        TLotHandler: MarginLotEventHandler + Clone,
+    {
+        let mut participant_ids: Vec<_> =
+            margin_manager.get_participants().keys().copied().collect();
+        participant_ids.sort();
+
+        let mut liquidated = Vec::new();
+        for participant_id in participant_ids {
+            if let Some(margin_ratio) = margin_manager.get_margin_ratio(participant_id)? {
+                if margin_ratio < 10000 {
+                    self.liquidate_account(
+                        participant_id,
+                        margin_ratio,
+                        order_manager,
+                        margin_manager,
+                        market_data_policy,
+                    )?;
+                    liquidated.push(participant_id);
+                }
+            }
+        }
+        Ok(liquidated)
+    }
+
+    /// Cancel open orders of account, then reduce its positions step by step
+    /// until it is back above maintenance margin
+    fn liquidate_account<TLotHandler>(
+        &self,
+        participant_id: usize,
+        mut margin_ratio: i128,
+        order_manager: &mut OrderManager,
+        margin_manager: &MarginManager<TLotHandler>,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<(), EngineError>
+    where
+        TLotHandler: MarginLotEventHandler + Clone,
+    {
+        self.send_event(LiquidationEvent::MarginCall {
+            participant_id,
+            margin_ratio,
+        });
+
+        let filter = MassCancelFilter {
+            participant_id: Some(participant_id),
+            ..Default::default()
+        };
+        let summary = order_manager.mass_cancel(&filter, margin_manager, market_data_policy);
+        self.send_event(LiquidationEvent::OrdersCancelled {
+            participant_id,
+            summary,
+        });
+
+        loop {
+            match margin_manager.get_margin_ratio(participant_id)? {
+                Some(ratio) if ratio < 10000 => margin_ratio = ratio,
+                ratio => {
+                    self.send_event(LiquidationEvent::Restored {
+                        participant_id,
+                        margin_ratio: ratio,
+                    });
+                    return Ok(());
+                }
+            }
+
+            let Some(order) =
+                self.new_liquidation_order(participant_id, order_manager, margin_manager)?
+            else {
+                break;
+            };
+            let outcome =
+                order_manager.place_order(order.clone(), margin_manager, market_data_policy);
+            let filled_quantity = outcome
+                .as_ref()
+                .map_or(0, |outcome| outcome.filled_quantity);
+            self.send_event(LiquidationEvent::OrderSubmitted {
+                participant_id,
+                order_id: order.order_id,
+                symbol: order.market.symbol.clone(),
+                side: order.get_side().unwrap_or(Side::Bid),
+                quantity: order.get_quantity().unwrap_or(0),
+                outcome,
+            });
+            if filled_quantity == 0 {
+                break;
+            }
+        }
+
+        self.send_event(LiquidationEvent::Stalled {
+            participant_id,
+            margin_ratio,
+        });
+        Ok(())
+    }
+
+    /// Market order closing part of the position requiring the most
+    /// maintenance margin, or `None` if there is no position to close
+    fn new_liquidation_order<TLotHandler>(
+        &self,
+        participant_id: usize,
+        order_manager: &mut OrderManager,
+        margin_manager: &MarginManager<TLotHandler>,
+    ) -> Result<Option<Rc<Order>>, EngineError>
+    where
+        TLotHandler: MarginLotEventHandler + Clone,
+    {
+        let Some(margin_model) = margin_manager.get_margin_model() else {
+            return Ok(None);
+        };
+        let Some(margin) = margin_manager.get_participants().get(&participant_id) else {
+            return Ok(None);
+        };
+        let margin = margin.borrow();
+
+        let mut largest_position = None;
+        for market in &self.markets {
+            let Some(asset_account) = margin.portfolio.get(&market.base_asset.symbol) else {
+                continue;
+            };
+            if !margin.portfolio.contains_key(&market.quote_asset.symbol) {
+                continue;
+            }
+            let balance = asset_account.borrow().get_balance();
+            let Some(quantity) = self.get_step_quantity(market, balance) else {
+                continue;
+            };
+            let rates = margin_model.get_margin_rates(&market.base_asset.symbol);
+            let value = margin_model
+                .get_value(&market.base_asset, balance)
+                .map_err(|err| EngineError::with_ids(participant_id, 0, err))?;
+            let maintenance_margin = value.abs() * rates.maintenance as i128;
+            if largest_position
+                .as_ref()
+                .is_none_or(|(largest_margin, _, _, _)| *largest_margin < maintenance_margin)
+            {
+                let side = if 0 < balance { Side::Ask } else { Side::Bid };
+                largest_position = Some((maintenance_margin, market, side, quantity));
+            }
+        }
+
+        Ok(largest_position.map(|(_, market, side, quantity)| {
+            Rc::new(Order {
+                market: market.clone(),
+                participant_id,
+                order_id: order_manager.new_order_id(),
+                client_order_id: None,
+                expire_time: None,
+                self_trade_prevention: None,
+                cancel_on_disconnect: false,
+                reduce_only: true,
+                order_data: OrderType::Market(MarketOrder { side, quantity }),
+            })
+        }))
+    }
+
+    /// Quantity in base decimals of market, which closes step rate of balance,
+    /// or the whole balance if step is too small, and never more than that
+    fn get_step_quantity(&self, market: &Market, balance: i128) -> Option<u64> {
+        let balance = u64::try_from(balance.unsigned_abs()).ok()?;
+        let to_market_decimals =
+            |quantity| change_decimals(quantity, market.base_asset.decimals, market.base_decimals);
+        let step_quantity = (balance as u128 * self.step_rate as u128 / 10000) as u64;
+        [step_quantity, balance]
+            .into_iter()
+            .filter_map(to_market_decimals)
+            .find(|quantity| 0 < *quantity)
+    }
+
+    fn send_event(&self, event: LiquidationEvent) {
+        self.event_handler.handle_liquidation_event(&event);
+    }
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct TrackLiquidationEvents {
+    events: std::cell::RefCell<Vec<LiquidationEvent>>,
+}
+
+#[cfg(test)]
+impl LiquidationEventHandler for TrackLiquidationEvents {
+    fn handle_liquidation_event(&self, event: &LiquidationEvent) {
+        self.events.borrow_mut().push(event.clone());
+    }
+}
+
+#[test]
+fn test_liquidation_price_path() {
+    use std::cell::RefCell;
+
+    use crate::{
+        clock::ManualClock,
+        margin::{
+            test_deposit, BalancePolicy, MarginLotEventHandlerNull, MarginModel, MarginRates,
+        },
+        market_data_policy::MarketDataNull,
+        order_book::OrderBook,
+        order_manager::{OrderBooks, FIRST_ASSIGNED_ORDER_ID},
+    };
+
+    let market = test_market();
+    let book = Rc::new(RefCell::new(OrderBook::new(market.clone())));
+    let mut order_manager = OrderManager::new(
+        Rc::new(OrderBooks::new(std::slice::from_ref(&book))),
+        Rc::new(ManualClock::default()),
+    );
+    let mut margin_manager = MarginManager::new(MarginLotEventHandlerNull);
+    for (participant_id, deposits, balance_policy) in [
+        (
+            1001,
+            vec![(&market.quote_asset, 100000)],
+            BalancePolicy::Margin { leverage: 100 },
+        ),
+        (
+            1002,
+            vec![
+                (&market.base_asset, 100000000),
+                (&market.quote_asset, 10000000),
+            ],
+            BalancePolicy::Spot,
+        ),
+    ] {
+        let mut account = margin_manager.add_account(participant_id).borrow_mut();
+        account
+            .add_asset_account(&market.base_asset)
+            .add_asset_account(&market.quote_asset)
+            .set_balance_policy(balance_policy);
+        for (asset, quantity) in deposits {
+            test_deposit(&mut account, asset, quantity);
+        }
+    }
+    let mut margin_model = MarginModel::new(&market.quote_asset, MarginRates::default());
+    margin_model
+        .set_mark_price("BTC", 5000000)
+        .set_margin_rates(
+            "BTC",
+            MarginRates {
+                initial: 1000,
+                maintenance: 500,
+            },
+        );
+    margin_manager.set_margin_model(margin_model);
+
+    // Participant 1001 buys 0.2 BTC at 50000.00 with 1000.00 USDT, and offers
+    // some of it, while 1002 bids below
+    for order in [
+        test_limit_order(&market, 1002, 1, Side::Ask, 5000000, 20000),
+        test_limit_order(&market, 1001, 2, Side::Bid, 5000000, 20000),
+        test_limit_order(&market, 1001, 3, Side::Ask, 6000000, 1000),
+        test_limit_order(&market, 1002, 4, Side::Bid, 4690000, 10000),
+        test_limit_order(&market, 1002, 5, Side::Bid, 4600000, 10000),
+    ] {
+        order_manager
+            .place_order(order, &margin_manager, &MarketDataNull)
+            .unwrap();
+    }
+
+    let events = Rc::new(TrackLiquidationEvents::default());
+    let mut liquidation_engine = LiquidationEngine::new(std::slice::from_ref(&market));
+    liquidation_engine.set_event_handler(events.clone());
+    let update_mark_price =
+        |price, order_manager: &mut OrderManager, margin_manager: &mut MarginManager<_>| {
+            let liquidated = liquidation_engine
+                .update_mark_price("BTC", price, order_manager, margin_manager, &MarketDataNull)
+                .unwrap();
+            (liquidated, events.events.take())
+        };
+    let filled = |order_id, quantity, price| LiquidationEvent::OrderSubmitted {
+        participant_id: 1001,
+        order_id,
+        symbol: "BTC/USDT".into(),
+        side: Side::Ask,
+        quantity,
+        outcome: Ok(OrderOutcome {
+            filled_quantity: quantity,
+            average_price: Some(price),
+            last_price: Some(price),
+            ..Default::default()
+        }),
+    };
+
+    // Equity of 600.00 USDT at 48000.00 is still above maintenance margin of 480.00 USDT
+    for price in [4900000, 4800000] {
+        assert_eq!(
+            update_mark_price(price, &mut order_manager, &mut margin_manager),
+            (vec![], vec![])
+        );
+    }
+
+    // Quarter of position is sold to restore margin ratio from 85.10% to 112.05%
+    assert_eq!(
+        update_mark_price(4700000, &mut order_manager, &mut margin_manager),
+        (
+            vec![1001],
+            vec![
+                LiquidationEvent::MarginCall {
+                    participant_id: 1001,
+                    margin_ratio: 8510,
+                },
+                LiquidationEvent::OrdersCancelled {
+                    participant_id: 1001,
+                    summary: MassCancelSummary {
+                        cancelled_orders: vec![((1001, 3), 1000)],
+                        errors: vec![],
+                    },
+                },
+                filled(FIRST_ASSIGNED_ORDER_ID, 5000, 4690000),
+                LiquidationEvent::Restored {
+                    participant_id: 1001,
+                    margin_ratio: Some(11205),
+                },
+            ]
+        )
+    );
+    let (liquidated, events_at_46000) =
+        update_mark_price(4600000, &mut order_manager, &mut margin_manager);
+    assert_eq!(liquidated, vec![1001]);
+    assert_eq!(
+        events_at_46000[2],
+        filled(FIRST_ASSIGNED_ORDER_ID + 1, 3750, 4690000)
+    );
+    assert_eq!(
+        events_at_46000[3],
+        LiquidationEvent::Restored {
+            participant_id: 1001,
+            margin_ratio: Some(10772),
+        }
+    );
+
+    // Without bids nothing can be sold, and account stays below maintenance margin
+    let filter = MassCancelFilter {
+        participant_id: Some(1002),
+        ..Default::default()
+    };
+    order_manager.mass_cancel(&filter, &margin_manager, &MarketDataNull);
+    let (liquidated, events_at_40000) =
+        update_mark_price(4000000, &mut order_manager, &mut margin_manager);
+    assert_eq!(liquidated, vec![1001]);
+    assert!(matches!(
+        events_at_40000.as_slice(),
+        [
+            LiquidationEvent::MarginCall { .. },
+            LiquidationEvent::OrdersCancelled { .. },
+            LiquidationEvent::OrderSubmitted {
+                outcome: Ok(OrderOutcome {
+                    filled_quantity: 0,
+                    ..
+                }),
+                ..
+            },
+            LiquidationEvent::Stalled {
+                participant_id: 1001,
+                margin_ratio,
+            },
+        ] if *margin_ratio < 0
+    ));
+}
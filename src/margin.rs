@@ -1,15 +1,20 @@
 use std::{
     cell::RefCell,
     collections::{HashMap, VecDeque},
-    error::Error,
     rc::Rc,
 };
 
 use itertools::FoldWhile::{Continue, Done};
 use itertools::Itertools;
 
-use crate::{execution_policy::ExecutionPolicy, order::*, order_book::OrderQuantity};
+use crate::{
+    error::{BookError, EngineError, EngineErrorKind, MarginError, NotFoundError},
+    execution_policy::ExecutionPolicy,
+    order::*,
+    order_book::OrderQuantity,
+};
 
+#[derive(Clone)]
 pub struct MarginLotTransaction {
     /// Order of the lot owner (can be aggressor or book order)
     pub order: Rc<Order>,
@@ -21,6 +26,7 @@ pub struct MarginLotTransaction {
 }
 
 /// One lot on once side of an asset on asset's account for one participant account
+#[derive(Clone)]
 pub struct MarginLot {
     /// Original quantity when lot was created
     pub quantity_orig: u64,
@@ -75,6 +81,13 @@ impl MarginLot {
             Some(quantity - left)
         }
     }
+
+    /// Reopen quantity closed by the most recent transaction, and forget it
+    pub fn undo_last_transaction(&mut self) {
+        if let Some(transaction) = self.transactions.pop_back() {
+            self.quantity_left += transaction.executed_quantity;
+        }
+    }
 }
 
 /// One side of and asset's account for one participant account
@@ -86,6 +99,12 @@ pub struct MarginSide {
     pub closed_lots: VecDeque<MarginLot>,
 }
 
+impl Default for MarginSide {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MarginSide {
     /// Brand new side of an account
     pub fn new() -> Self {
@@ -154,7 +173,7 @@ impl MarginSide {
         cb: impl FnOnce(&MarginLot),
     ) {
         self.create_lot(quantity, order, price);
-        self.open_lots.back().inspect(|x| cb(*x));
+        self.open_lots.back().inspect(|x| cb(x));
     }
 
     /// Close lots for given quantity and tell how many were closed
@@ -210,11 +229,32 @@ impl MarginSide {
     }
 }
 
+/// Change of one side of an asset's account, which can be undone
+pub enum MarginChange {
+    /// Promise was made for given quantity
+    Promised(u64),
+    /// Promise was cancelled for given quantity
+    PromiseCancelled(u64),
+    Locked(u64),
+    Unlocked(u64),
+    Committed(u64),
+    /// Committed quantity was taken by the opposite side
+    CommitTaken(u64),
+    /// Lot was created at the back of open lots
+    LotCreated,
+    /// Lot at the front of open lots was partially closed
+    LotUpdated,
+    /// Lot was closed and taken from the front of open lots
+    LotClosed(MarginLot),
+}
+
 /// Account of an asset for one participant's account
 pub struct MarginAssetAccount {
     pub asset: Rc<Asset>,
     pub received: MarginSide,
     pub delivered: MarginSide,
+    /// Changes recorded since journal began, if it did
+    journal: Option<Vec<(Side, MarginChange)>>,
 }
 
 /// Handles open and close lot events
@@ -248,43 +288,272 @@ pub trait MarginLotEventHandler {
     );
 }
 
+/// Kind of lot event
+enum MarginLotEventKind {
+    Opened,
+    Updated,
+    Closed,
+}
+
+/// Lot event held back until transaction with other party ends
+struct PendingLotEvent {
+    kind: MarginLotEventKind,
+    asset: Rc<Asset>,
+    side: Side,
+    lot: MarginLot,
+    order: Rc<Order>,
+    price: u64,
+    account_id: usize,
+}
+
+impl PendingLotEvent {
+    fn send(self, event_handler: &impl MarginLotEventHandler) {
+        let Self {
+            kind,
+            asset,
+            side,
+            lot,
+            order,
+            price,
+            account_id,
+        } = self;
+        match kind {
+            MarginLotEventKind::Opened => {
+                event_handler.handle_lot_opened(asset, side, &lot, order, price, account_id)
+            }
+            MarginLotEventKind::Updated => {
+                event_handler.handle_lot_updated(asset, side, &lot, order, price, account_id)
+            }
+            MarginLotEventKind::Closed => {
+                event_handler.handle_lot_closed(asset, side, lot, order, price, account_id)
+            }
+        }
+    }
+}
+
+/// Lot events of transactions, which can still be rolled back
+#[derive(Default)]
+struct PendingLotEvents(RefCell<Vec<PendingLotEvent>>);
+
+impl MarginLotEventHandler for PendingLotEvents {
+    fn handle_lot_opened(
+        &self,
+        asset: Rc<Asset>,
+        side: Side,
+        lot: &MarginLot,
+        order: Rc<Order>,
+        price: u64,
+        account_id: usize,
+    ) {
+        self.0.borrow_mut().push(PendingLotEvent {
+            kind: MarginLotEventKind::Opened,
+            asset,
+            side,
+            lot: lot.clone(),
+            order,
+            price,
+            account_id,
+        });
+    }
+    fn handle_lot_updated(
+        &self,
+        asset: Rc<Asset>,
+        side: Side,
+        lot: &MarginLot,
+        order: Rc<Order>,
+        price: u64,
+        account_id: usize,
+    ) {
+        self.0.borrow_mut().push(PendingLotEvent {
+            kind: MarginLotEventKind::Updated,
+            asset,
+            side,
+            lot: lot.clone(),
+            order,
+            price,
+            account_id,
+        });
+    }
+    fn handle_lot_closed(
+        &self,
+        asset: Rc<Asset>,
+        side: Side,
+        lot: MarginLot,
+        order: Rc<Order>,
+        price: u64,
+        account_id: usize,
+    ) {
+        self.0.borrow_mut().push(PendingLotEvent {
+            kind: MarginLotEventKind::Closed,
+            asset,
+            side,
+            lot,
+            order,
+            price,
+            account_id,
+        });
+    }
+}
+
 impl MarginAssetAccount {
     pub fn new(asset: &Rc<Asset>) -> Self {
         Self {
             asset: asset.clone(),
             received: MarginSide::new(),
             delivered: MarginSide::new(),
+            journal: None,
+        }
+    }
+
+    /// Quantity that can be delivered without borrowing: committed long, minus
+    /// committed short, minus open delivery promises, minus locked delivery
+    pub fn get_available_balance(&self) -> i128 {
+        self.received.quantity_committed as i128
+            - self.delivered.quantity_committed as i128
+            - self.delivered.quantity_open as i128
+            - self.delivered.quantity_locked as i128
+    }
+
+    /// Net committed quantity, i.e. long minus short
+    pub fn get_balance(&self) -> i128 {
+        self.received.quantity_committed as i128 - self.delivered.quantity_committed as i128
+    }
+
+    /// Net quantity held, counting also open and locked receipts and deliveries
+    pub fn get_position(&self) -> i128 {
+        let get_total = |side: &MarginSide| {
+            side.quantity_committed as i128
+                + side.quantity_open as i128
+                + side.quantity_locked as i128
+        };
+        get_total(&self.received) - get_total(&self.delivered)
+    }
+
+    /// Start recording changes, so that they can be rolled back, unless
+    /// already recording
+    pub fn begin_journal(&mut self) {
+        self.journal.get_or_insert_with(Vec::new);
+    }
+
+    /// Stop recording changes, and keep them
+    pub fn end_journal(&mut self) {
+        self.journal = None;
+    }
+
+    /// Undo changes recorded since journal began, and stop recording
+    pub fn rollback_journal(&mut self) {
+        for (side, change) in self.journal.take().unwrap_or_default().into_iter().rev() {
+            let margin_side = match side {
+                Side::Bid => &mut self.received,
+                Side::Ask => &mut self.delivered,
+            };
+            match change {
+                MarginChange::Promised(quantity) => margin_side.quantity_open -= quantity,
+                MarginChange::PromiseCancelled(quantity) => margin_side.quantity_open += quantity,
+                MarginChange::Locked(quantity) => margin_side.quantity_locked -= quantity,
+                MarginChange::Unlocked(quantity) => margin_side.quantity_locked += quantity,
+                MarginChange::Committed(quantity) => margin_side.quantity_committed -= quantity,
+                MarginChange::CommitTaken(quantity) => margin_side.quantity_committed += quantity,
+                MarginChange::LotCreated => {
+                    margin_side.open_lots.pop_back();
+                }
+                MarginChange::LotUpdated => {
+                    if let Some(lot) = margin_side.open_lots.front_mut() {
+                        lot.undo_last_transaction();
+                    }
+                }
+                MarginChange::LotClosed(mut lot) => {
+                    lot.undo_last_transaction();
+                    margin_side.open_lots.push_front(lot);
+                }
+            }
+        }
+    }
+
+    fn record(&mut self, side: Side, change: MarginChange) {
+        if let Some(journal) = &mut self.journal {
+            journal.push((side, change));
+        }
+    }
+
+    /// Record lots closed and updated on given side by matching, which left
+    /// given quantity unmatched
+    fn record_matched_lots(&mut self, side: Side, quantity_left: Option<u64>) {
+        let Some(journal) = &mut self.journal else {
+            return;
+        };
+        let margin_side = match side {
+            Side::Bid => &self.received,
+            Side::Ask => &self.delivered,
+        };
+        journal.extend(
+            margin_side
+                .closed_lots
+                .iter()
+                .map(|lot| (side, MarginChange::LotClosed(lot.clone()))),
+        );
+        // Matching stops at partially closed lot, or else leaves some quantity
+        if quantity_left.is_none() {
+            journal.push((side, MarginChange::LotUpdated));
+        }
+    }
+
+    /// Commit locked quantity on given side, after taking committed quantity
+    /// of the opposite side
+    fn commit_side(&mut self, side: Side, quantity: u64) {
+        let (margin_side, opposite_side) = match side {
+            Side::Bid => (&mut self.received, &mut self.delivered),
+            Side::Ask => (&mut self.delivered, &mut self.received),
+        };
+        let opposite_committed = opposite_side.quantity_committed;
+        let commit_quantity = opposite_side.will_commit_opposite_side(quantity);
+        let taken_quantity = opposite_committed - opposite_side.quantity_committed;
+        margin_side.commit_transaction(quantity, commit_quantity);
+        self.record(side.opposite(), MarginChange::CommitTaken(taken_quantity));
+        self.record(side, MarginChange::Unlocked(quantity));
+        if let Some(commit_quantity) = commit_quantity {
+            self.record(side, MarginChange::Committed(commit_quantity));
         }
     }
 
     /// Promise possible receipt in future (happens when you place new order on the book)
     pub fn promise_receipt(&mut self, quantity: u64) {
         self.received.promise_transaction(quantity);
+        self.record(Side::Bid, MarginChange::Promised(quantity));
     }
 
     /// Promise possible delivery in future (happens when you place new order on the book)
     pub fn promise_delivery(&mut self, quantity: u64) {
         self.delivered.promise_transaction(quantity);
+        self.record(Side::Ask, MarginChange::Promised(quantity));
     }
 
     /// Cancel the promise of future receipt (either cancel or execution happened)
     pub fn cancel_receipt_promise(&mut self, quantity: u64) {
+        let quantity_open = self.received.quantity_open;
         self.received.cancel_transaction_promise(quantity);
+        let quantity = quantity_open - self.received.quantity_open;
+        self.record(Side::Bid, MarginChange::PromiseCancelled(quantity));
     }
 
     /// Cancel the promise of future delivery (either cancel or execution happened)
     pub fn cancel_delivery_promise(&mut self, quantity: u64) {
+        let quantity_open = self.delivered.quantity_open;
         self.delivered.cancel_transaction_promise(quantity);
+        let quantity = quantity_open - self.delivered.quantity_open;
+        self.record(Side::Ask, MarginChange::PromiseCancelled(quantity));
     }
 
     /// Begin receiving lot of an asset, which will produce transaction
     pub fn begin_receipt(&mut self, quantity: u64) {
         self.received.begin_transaction(quantity);
+        self.record(Side::Bid, MarginChange::Locked(quantity));
     }
 
     /// Begin delivering lot of an asset, which will produce transaction
     pub fn begin_delivery(&mut self, quantity: u64) {
         self.delivered.begin_transaction(quantity);
+        self.record(Side::Ask, MarginChange::Locked(quantity));
     }
 
     /// Commit receipt of a lot of an asset (will match existing lots on Short side)
@@ -297,7 +566,7 @@ impl MarginAssetAccount {
         event_handler: &impl MarginLotEventHandler,
     ) {
         let order_2 = order.clone();
-        if let Some(quantity) =
+        let quantity_left =
             self.delivered
                 .match_lots_with_callback(quantity, order.clone(), price, |lot| {
                     event_handler.handle_lot_updated(
@@ -308,8 +577,9 @@ impl MarginAssetAccount {
                         price,
                         account_id,
                     )
-                })
-        {
+                });
+        self.record_matched_lots(Side::Ask, quantity_left);
+        if let Some(quantity) = quantity_left {
             self.received
                 .create_lot_with_callback(quantity, order_2.clone(), price, |lot| {
                     event_handler.handle_lot_opened(
@@ -321,9 +591,9 @@ impl MarginAssetAccount {
                         account_id,
                     )
                 });
+            self.record(Side::Bid, MarginChange::LotCreated);
         }
-        self.received
-            .commit_transaction(quantity, self.delivered.will_commit_opposite_side(quantity));
+        self.commit_side(Side::Bid, quantity);
 
         self.delivered.flush_closed_lots(|lot| {
             event_handler.handle_lot_closed(
@@ -347,7 +617,7 @@ impl MarginAssetAccount {
         event_handler: &impl MarginLotEventHandler,
     ) {
         let order_2 = order.clone();
-        if let Some(quantity) =
+        let quantity_left =
             self.received
                 .match_lots_with_callback(quantity, order.clone(), price, |lot| {
                     event_handler.handle_lot_updated(
@@ -358,8 +628,9 @@ impl MarginAssetAccount {
                         price,
                         account_id,
                     )
-                })
-        {
+                });
+        self.record_matched_lots(Side::Bid, quantity_left);
+        if let Some(quantity) = quantity_left {
             self.delivered
                 .create_lot_with_callback(quantity, order_2.clone(), price, |lot| {
                     event_handler.handle_lot_opened(
@@ -371,9 +642,9 @@ impl MarginAssetAccount {
                         account_id,
                     )
                 });
+            self.record(Side::Ask, MarginChange::LotCreated);
         }
-        self.delivered
-            .commit_transaction(quantity, self.received.will_commit_opposite_side(quantity));
+        self.commit_side(Side::Ask, quantity);
 
         self.received.flush_closed_lots(|lot| {
             event_handler.handle_lot_closed(
@@ -388,28 +659,148 @@ impl MarginAssetAccount {
     }
 }
 
+type SharedAssetAccount = Rc<RefCell<MarginAssetAccount>>;
+
+/// How much of an asset participant can deliver
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BalancePolicy {
+    /// Deliver no more than available balance, so that it never goes negative
+    #[default]
+    Spot,
+    /// Borrow up to leverage minus one times equity, where equity and borrowed
+    /// quantity are valued in quote asset of the market at price of the order
+    Margin { leverage: u64 },
+}
+
+/// Margin required for position in an asset, in basis points of its value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MarginRates {
+    /// Required to open position, including open orders
+    pub initial: u64,
+    /// Required to keep position, below which account is to be liquidated
+    pub maintenance: u64,
+}
+
+/// Mark prices and margin rates, by which accounts are valued in reporting asset
+pub struct MarginModel {
+    pub reporting_asset: Rc<Asset>,
+    /// Price of one whole unit of asset in reporting asset, by symbol of asset
+    mark_prices: HashMap<String, u64>,
+    margin_rates: HashMap<String, MarginRates>,
+    /// Rates of assets without their own rates
+    default_rates: MarginRates,
+}
+
+impl MarginModel {
+    pub fn new(reporting_asset: &Rc<Asset>, default_rates: MarginRates) -> Self {
+        Self {
+            reporting_asset: reporting_asset.clone(),
+            mark_prices: HashMap::new(),
+            margin_rates: HashMap::new(),
+            default_rates,
+        }
+    }
+
+    /// Set price of one whole unit of asset, in decimals of reporting asset
+    pub fn set_mark_price(&mut self, symbol: &str, price: u64) -> &mut Self {
+        self.mark_prices.insert(symbol.into(), price);
+        self
+    }
+
+    pub fn get_mark_price(&self, symbol: &str) -> Option<u64> {
+        if symbol == self.reporting_asset.symbol {
+            10u64.checked_pow(self.reporting_asset.decimals as u32)
+        } else {
+            self.mark_prices.get(symbol).copied()
+        }
+    }
+
+    pub fn set_margin_rates(&mut self, symbol: &str, margin_rates: MarginRates) -> &mut Self {
+        self.margin_rates.insert(symbol.into(), margin_rates);
+        self
+    }
+
+    pub fn get_margin_rates(&self, symbol: &str) -> MarginRates {
+        self.margin_rates
+            .get(symbol)
+            .copied()
+            .unwrap_or(self.default_rates)
+    }
+
+    /// Value quantity of an asset (negative for short) in reporting asset
+    pub fn get_value(&self, asset: &Asset, quantity: i128) -> Result<i128, EngineErrorKind> {
+        if quantity == 0 {
+            return Ok(0);
+        }
+        let price = self
+            .get_mark_price(&asset.symbol)
+            .ok_or_else(|| MarginError::MarkPriceNotFound(asset.symbol.clone()))?;
+        quantity
+            .checked_mul(price as i128)
+            .and_then(|value| value.checked_div(10i128.checked_pow(asset.decimals as u32)?))
+            .ok_or(EngineErrorKind::Overflow)
+    }
+
+    /// Margin required for quantity of an asset, at given margin rate
+    ///
+    /// Reporting asset requires no margin. Its positive balance is equity,
+    /// and borrowing it only pays for positions in other assets, which are
+    /// margined at their own rates.
+    fn get_margin(
+        &self,
+        asset: &Asset,
+        quantity: i128,
+        get_rate: impl Fn(MarginRates) -> u64,
+    ) -> Result<i128, EngineErrorKind> {
+        if asset.symbol == self.reporting_asset.symbol {
+            return Ok(0);
+        }
+        let rate = get_rate(self.get_margin_rates(&asset.symbol)) as i128;
+        self.get_value(asset, quantity)?
+            .abs()
+            .checked_mul(rate)
+            .map(|margin| margin / 10000)
+            .ok_or(EngineErrorKind::Overflow)
+    }
+}
+
 /// Margin account of a single participant
 pub struct MarginTradingAccount<TLotHandler>
-where 
-    TLotHandler: MarginLotEventHandler
+where
+    TLotHandler: MarginLotEventHandler,
 {
     pub account_id: usize,
     pub portfolio: HashMap<String, Rc<RefCell<MarginAssetAccount>>>,
+    balance_policy: BalancePolicy,
     margin_lot_event_handler: TLotHandler,
+    /// Lot events are sent to event handler only when transaction with
+    /// other party ends, so that rolled back ones are never seen
+    pending_lot_events: PendingLotEvents,
 }
 
 impl<TLotHandler> MarginTradingAccount<TLotHandler>
-where 
-    TLotHandler: MarginLotEventHandler
+where
+    TLotHandler: MarginLotEventHandler,
 {
     pub fn new(account_id: usize, margin_lot_event_handler: TLotHandler) -> Self {
         Self {
             account_id,
             portfolio: HashMap::new(),
+            balance_policy: BalancePolicy::default(),
             margin_lot_event_handler,
+            pending_lot_events: PendingLotEvents::default(),
         }
     }
 
+    pub fn set_balance_policy(&mut self, balance_policy: BalancePolicy) -> &mut Self {
+        self.balance_policy = balance_policy;
+        self
+    }
+
+    pub fn get_balance_policy(&self) -> BalancePolicy {
+        self.balance_policy
+    }
+
     /// Add account for an asset
     pub fn add_asset_account(&mut self, asset: &Rc<Asset>) -> &mut Self {
         self.portfolio
@@ -423,15 +814,201 @@ where
         self.portfolio.get(asset)
     }
 
+    /// Check that participant can deliver for the order on given side, which
+    /// is not promised yet
+    fn check_delivery(
+        &self,
+        order: &Order,
+        side: Side,
+        quantity: u64,
+        price: u64,
+        base_asset_account: &MarginAssetAccount,
+        quote_asset_account: &MarginAssetAccount,
+    ) -> Result<(), EngineError> {
+        let overflow = || EngineError::new(order, EngineErrorKind::Overflow);
+        let (base_quantity, quote_value) = order
+            .get_quantity_and_value(quantity, price)
+            .ok_or_else(overflow)?;
+        let base_available = base_asset_account.get_available_balance();
+        let quote_available = quote_asset_account.get_available_balance();
+        let (base_available_after, quote_available_after, delivery_account) = match side {
+            Side::Bid => (
+                base_available + base_quantity as i128,
+                quote_available - quote_value as i128,
+                quote_asset_account,
+            ),
+            Side::Ask => (
+                base_available - base_quantity as i128,
+                quote_available + quote_value as i128,
+                base_asset_account,
+            ),
+        };
+        let is_sufficient = match self.balance_policy {
+            BalancePolicy::Spot => 0 <= base_available_after.min(quote_available_after),
+            BalancePolicy::Margin { leverage } => {
+                // Value of base asset quantity in quote asset
+                let market = &order.market;
+                let get_value = |quantity: i128| {
+                    let value = u64::try_from(quantity.unsigned_abs())
+                        .ok()
+                        .and_then(|quantity| {
+                            change_decimals(
+                                quantity,
+                                market.base_asset.decimals,
+                                market.base_decimals,
+                            )
+                        })
+                        .and_then(|quantity| order.get_quantity_and_value(quantity, price))
+                        .ok_or_else(overflow)?
+                        .1;
+                    Ok::<_, EngineError>(value as i128 * quantity.signum())
+                };
+                let get_borrowed = |base_available: i128, quote_available: i128| {
+                    Ok::<_, EngineError>(
+                        (-quote_available).max(0) + get_value((-base_available).max(0))?,
+                    )
+                };
+                let equity = quote_asset_account.get_position()
+                    + get_value(base_asset_account.get_position())?;
+                let borrowed_before = get_borrowed(base_available, quote_available)?;
+                let borrowed = get_borrowed(base_available_after, quote_available_after)?;
+                // Orders reducing what is borrowed are allowed even without equity
+                borrowed <= borrowed_before
+                    || borrowed <= leverage.saturating_sub(1) as i128 * equity
+            }
+        };
+        if is_sufficient {
+            Ok(())
+        } else {
+            Err(EngineError::new(
+                order,
+                MarginError::InsufficientBalance(delivery_account.asset.symbol.clone()),
+            ))
+        }
+    }
+
+    /// Check that reduce-only order would not increase committed position in
+    /// base asset of its market, nor turn it to the other side, by executing
+    /// given quantity
+    fn check_reduce_only(&self, order: &Order, quantity: u64) -> Result<(), EngineError> {
+        if !order.reduce_only {
+            return Ok(());
+        }
+        let market = &order.market;
+        let side = order
+            .get_side()
+            .ok_or_else(|| EngineError::new(order, BookError::InvalidOrderType))?;
+        let quantity = change_decimals(quantity, market.base_decimals, market.base_asset.decimals)
+            .ok_or_else(|| EngineError::new(order, EngineErrorKind::Overflow))?;
+        let balance = self
+            .get_asset_account(&market.base_asset.symbol)
+            .map_or(0, |asset_account| asset_account.borrow().get_balance());
+        let position = match side {
+            Side::Bid => -balance,
+            Side::Ask => balance,
+        };
+        if quantity as i128 <= position {
+            Ok(())
+        } else {
+            Err(EngineError::new(order, MarginError::PositionNotReduced))
+        }
+    }
+
+    /// Sum of values of committed balances, in reporting asset
+    pub fn get_equity(&self, margin_model: &MarginModel) -> Result<i128, EngineErrorKind> {
+        self.portfolio
+            .values()
+            .try_fold(0i128, |equity, asset_account| {
+                let asset_account = asset_account.borrow();
+                let value =
+                    margin_model.get_value(&asset_account.asset, asset_account.get_balance())?;
+                equity.checked_add(value).ok_or(EngineErrorKind::Overflow)
+            })
+    }
+
+    /// Margin required for positions as if all open orders were executed
+    pub fn get_initial_margin(&self, margin_model: &MarginModel) -> Result<i128, EngineErrorKind> {
+        self.portfolio
+            .values()
+            .try_fold(0i128, |margin, asset_account| {
+                let asset_account = asset_account.borrow();
+                let position = asset_account.get_position();
+                let position_margin =
+                    margin_model
+                        .get_margin(&asset_account.asset, position, |rates| rates.initial)?;
+                margin
+                    .checked_add(position_margin)
+                    .ok_or(EngineErrorKind::Overflow)
+            })
+    }
+
+    /// Margin required for committed balances
+    pub fn get_maintenance_margin(
+        &self,
+        margin_model: &MarginModel,
+    ) -> Result<i128, EngineErrorKind> {
+        self.portfolio
+            .values()
+            .try_fold(0i128, |margin, asset_account| {
+                let asset_account = asset_account.borrow();
+                let balance = asset_account.get_balance();
+                let balance_margin =
+                    margin_model
+                        .get_margin(&asset_account.asset, balance, |rates| rates.maintenance)?;
+                margin
+                    .checked_add(balance_margin)
+                    .ok_or(EngineErrorKind::Overflow)
+            })
+    }
+
+    /// Equity to maintenance margin in basis points, or `None` if no margin is
+    /// required (below 10000 the account is to be liquidated)
+    pub fn get_margin_ratio(
+        &self,
+        margin_model: &MarginModel,
+    ) -> Result<Option<i128>, EngineErrorKind> {
+        let maintenance_margin = self.get_maintenance_margin(margin_model)?;
+        let equity = self.get_equity(margin_model)?;
+        if maintenance_margin == 0 {
+            Ok(None)
+        } else {
+            equity
+                .checked_mul(10000)
+                .map(|equity| Some(equity / maintenance_margin))
+                .ok_or(EngineErrorKind::Overflow)
+        }
+    }
+
+    /// Check that equity covers initial margin, unless order did not add to
+    /// initial margin required before it
+    fn check_initial_margin(
+        &self,
+        margin_model: &MarginModel,
+        order: &Order,
+        initial_margin_before: i128,
+    ) -> Result<(), EngineError> {
+        let initial_margin = self
+            .get_initial_margin(margin_model)
+            .map_err(|err| EngineError::new(order, err))?;
+        let equity = self
+            .get_equity(margin_model)
+            .map_err(|err| EngineError::new(order, err))?;
+        if initial_margin <= equity || initial_margin <= initial_margin_before {
+            Ok(())
+        } else {
+            Err(EngineError::new(order, MarginError::InsufficientMargin))
+        }
+    }
+
     /// Transfer to/from account of an asset (can be deposit or withdrawal)
-    pub fn transfer(&mut self, order: Rc<Order>, price: u64) -> Result<(), Box<dyn Error>> {
+    pub fn transfer(&mut self, order: Rc<Order>, price: u64) -> Result<(), EngineError> {
         if let Some(asset_account) = self.get_asset_account(&order.market.base_asset.symbol) {
             let mut asset_account_mut = asset_account.borrow_mut();
             match order.order_data {
                 OrderType::Deposit(quantity) => {
                     let (base_quantity, _) = order
                         .get_quantity_and_value(quantity, price)
-                        .ok_or("Mathematical overflow")?;
+                        .ok_or_else(|| EngineError::new(&order, EngineErrorKind::Overflow))?;
                     asset_account_mut.begin_receipt(base_quantity);
                     asset_account_mut.commit_receipt(
                         base_quantity,
@@ -443,10 +1020,18 @@ where
                     Ok(())
                 }
                 OrderType::Withdraw(quantity) => {
-                    // TODO: Check available balance/margin
                     let (base_quantity, _) = order
                         .get_quantity_and_value(quantity, price)
-                        .ok_or("Mathematical overflow")?;
+                        .ok_or_else(|| EngineError::new(&order, EngineErrorKind::Overflow))?;
+                    // Borrowed quantity cannot be withdrawn, whatever the balance policy
+                    if asset_account_mut.get_available_balance() < base_quantity as i128 {
+                        return Err(EngineError::new(
+                            &order,
+                            MarginError::InsufficientBalance(
+                                asset_account_mut.asset.symbol.clone(),
+                            ),
+                        ));
+                    }
                     asset_account_mut.begin_delivery(base_quantity);
                     asset_account_mut.commit_delivery(
                         base_quantity,
@@ -457,39 +1042,46 @@ where
                     );
                     Ok(())
                 }
-                _ => Err("Invalid transfer type".into()),
+                _ => Err(EngineError::new(&order, MarginError::InvalidTransferType)),
             }
         } else {
-            Err(format!(
-                "Asset account for {} not found",
-                order.market.base_asset.symbol
-            )
-            .into())
+            Err(EngineError::new(
+                &order,
+                MarginError::AssetAccountNotFound(order.market.base_asset.symbol.clone()),
+            ))
         }
     }
 
     /// Account for placing an order
-    pub fn place_order(&mut self, book_order: &mut OrderQuantity) -> Result<(), Box<dyn Error>> {
-        // TODO: Check avaliable balance/margin for open orders
-
-        let limit = match &book_order.order.order_data {
-            OrderType::Limit(limit) => Some(limit),
-            _ => None,
-        }
-        .ok_or("Invalid order type to place on book")?;
+    pub fn place_order(&mut self, book_order: &mut OrderQuantity) -> Result<(), EngineError> {
+        let limit = book_order
+            .order
+            .get_book_limit()
+            .ok_or_else(|| EngineError::new(&book_order.order, BookError::InvalidOrderType))?;
 
         let base_symbol = &book_order.order.market.base_asset.symbol;
         let quote_symbol = &book_order.order.market.quote_asset.symbol;
 
-        if let Some(base_asset_account) = self.get_asset_account(&base_symbol) {
-            if let Some(quote_asset_account) = self.get_asset_account(&quote_symbol) {
+        if let Some(base_asset_account) = self.get_asset_account(base_symbol) {
+            if let Some(quote_asset_account) = self.get_asset_account(quote_symbol) {
                 let mut base_asset_account = base_asset_account.borrow_mut();
                 let mut quote_asset_account = quote_asset_account.borrow_mut();
 
                 let (base_quantity, quote_value) = book_order
                     .order
-                    .get_quantity_and_value(book_order.quantity, limit.price)
-                    .ok_or("Mathematical overflow")?;
+                    .get_quantity_and_value(book_order.get_total_quantity(), limit.price)
+                    .ok_or_else(|| {
+                        EngineError::new(&book_order.order, EngineErrorKind::Overflow)
+                    })?;
+
+                self.check_delivery(
+                    &book_order.order,
+                    limit.side,
+                    book_order.get_total_quantity(),
+                    limit.price,
+                    &base_asset_account,
+                    &quote_asset_account,
+                )?;
 
                 match limit.side {
                     Side::Ask => {
@@ -503,43 +1095,46 @@ where
                 }
                 Ok(())
             } else {
-                Err(format!(
-                    "Margin data not found for {}",
-                    book_order.order.market.quote_asset.symbol
-                )
-                .into())
+                Err(EngineError::new(
+                    &book_order.order,
+                    MarginError::AssetAccountNotFound(
+                        book_order.order.market.quote_asset.symbol.clone(),
+                    ),
+                ))
             }
         } else {
-            Err(format!(
-                "Margin data not found for {}",
-                book_order.order.market.base_asset.symbol
-            )
-            .into())
+            Err(EngineError::new(
+                &book_order.order,
+                MarginError::AssetAccountNotFound(
+                    book_order.order.market.base_asset.symbol.clone(),
+                ),
+            ))
         }
     }
 
     /// Account for cancelling an order
-    pub fn cancel_order(&mut self, book_order: &mut OrderQuantity) -> Result<(), Box<dyn Error>> {
+    pub fn cancel_order(&mut self, book_order: &mut OrderQuantity) -> Result<(), EngineError> {
         // TODO: Check avaliable balance/margin for open orders
 
-        let limit = match &book_order.order.order_data {
-            OrderType::Limit(limit) => Some(limit),
-            _ => None,
-        }
-        .ok_or("Invalid order type to place on book")?;
+        let Some(limit) = book_order.order.get_book_limit() else {
+            // Remainder of immediate order was never placed, so nothing was promised
+            return Ok(());
+        };
 
         let base_symbol = &book_order.order.market.base_asset.symbol;
         let quote_symbol = &book_order.order.market.quote_asset.symbol;
 
-        if let Some(base_asset_account) = self.get_asset_account(&base_symbol) {
-            if let Some(quote_asset_account) = self.get_asset_account(&quote_symbol) {
+        if let Some(base_asset_account) = self.get_asset_account(base_symbol) {
+            if let Some(quote_asset_account) = self.get_asset_account(quote_symbol) {
                 let mut base_asset_account = base_asset_account.borrow_mut();
                 let mut quote_asset_account = quote_asset_account.borrow_mut();
 
                 let (base_quantity, quote_value) = book_order
                     .order
-                    .get_quantity_and_value(book_order.quantity, limit.price)
-                    .ok_or("Mathematical overflow")?;
+                    .get_quantity_and_value(book_order.get_total_quantity(), limit.price)
+                    .ok_or_else(|| {
+                        EngineError::new(&book_order.order, EngineErrorKind::Overflow)
+                    })?;
 
                 match limit.side {
                     Side::Ask => {
@@ -547,24 +1142,26 @@ where
                         quote_asset_account.cancel_receipt_promise(quote_value);
                     }
                     Side::Bid => {
-                        base_asset_account.cancel_delivery_promise(base_quantity);
+                        base_asset_account.cancel_receipt_promise(base_quantity);
                         quote_asset_account.cancel_delivery_promise(quote_value);
                     }
                 }
                 Ok(())
             } else {
-                Err(format!(
-                    "Margin data not found for {}",
-                    book_order.order.market.quote_asset.symbol
-                )
-                .into())
+                Err(EngineError::new(
+                    &book_order.order,
+                    MarginError::AssetAccountNotFound(
+                        book_order.order.market.quote_asset.symbol.clone(),
+                    ),
+                ))
             }
         } else {
-            Err(format!(
-                "Margin data not found for {}",
-                book_order.order.market.base_asset.symbol
-            )
-            .into())
+            Err(EngineError::new(
+                &book_order.order,
+                MarginError::AssetAccountNotFound(
+                    book_order.order.market.base_asset.symbol.clone(),
+                ),
+            ))
         }
     }
 
@@ -575,14 +1172,11 @@ where
         order_quantity: &OrderQuantity,
         book_order: &OrderQuantity,
         is_aggressor: bool,
-    ) -> Result<(), Box<dyn Error>> {
-        // TODO: Check avaliable balance/margin for open orders
-
-        let limit = match &book_order.order.order_data {
-            OrderType::Limit(limit) => Some(limit),
-            _ => None,
-        }
-        .ok_or("Invalid order type to place on book")?;
+    ) -> Result<(), EngineError> {
+        let limit = book_order
+            .order
+            .get_book_limit()
+            .ok_or_else(|| EngineError::new(&book_order.order, BookError::InvalidOrderType))?;
 
         let side = if is_aggressor {
             limit.side.opposite()
@@ -592,15 +1186,32 @@ where
         let base_symbol = &order_quantity.order.market.base_asset.symbol;
         let quote_symbol = &order_quantity.order.market.quote_asset.symbol;
 
-        if let Some(base_asset_account) = self.get_asset_account(&base_symbol) {
-            if let Some(quote_asset_account) = self.get_asset_account(&quote_symbol) {
+        if let Some(base_asset_account) = self.get_asset_account(base_symbol) {
+            if let Some(quote_asset_account) = self.get_asset_account(quote_symbol) {
                 let mut base_asset_account = base_asset_account.borrow_mut();
                 let mut quote_asset_account = quote_asset_account.borrow_mut();
 
                 let (base_quantity, quote_value) = order_quantity
                     .order
                     .get_quantity_and_value(*executed_quantity, limit.price)
-                    .ok_or("Mathematical overflow")?;
+                    .ok_or_else(|| {
+                        EngineError::new(&order_quantity.order, EngineErrorKind::Overflow)
+                    })?;
+
+                // Book order was checked when it was placed, and delivers what it promised
+                if is_aggressor {
+                    self.check_delivery(
+                        &order_quantity.order,
+                        side,
+                        *executed_quantity,
+                        limit.price,
+                        &base_asset_account,
+                        &quote_asset_account,
+                    )?;
+                }
+
+                base_asset_account.begin_journal();
+                quote_asset_account.begin_journal();
 
                 match side {
                     Side::Ask => {
@@ -623,18 +1234,20 @@ where
 
                 Ok(())
             } else {
-                Err(format!(
-                    "Margin data not found for {}",
-                    order_quantity.order.market.quote_asset.symbol
-                )
-                .into())
+                Err(EngineError::new(
+                    &order_quantity.order,
+                    MarginError::AssetAccountNotFound(
+                        order_quantity.order.market.quote_asset.symbol.clone(),
+                    ),
+                ))
             }
         } else {
-            Err(format!(
-                "Margin data not found for {}",
-                order_quantity.order.market.base_asset.symbol
-            )
-            .into())
+            Err(EngineError::new(
+                &order_quantity.order,
+                MarginError::AssetAccountNotFound(
+                    order_quantity.order.market.base_asset.symbol.clone(),
+                ),
+            ))
         }
     }
 
@@ -645,14 +1258,13 @@ where
         order_quantity: &OrderQuantity,
         book_order: &OrderQuantity,
         is_aggressor: bool,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), EngineError> {
         // TODO: Unrepeat this code!
 
-        let limit = match &book_order.order.order_data {
-            OrderType::Limit(limit) => Some(limit),
-            _ => None,
-        }
-        .ok_or("Invalid order type to place on book")?;
+        let limit = book_order
+            .order
+            .get_book_limit()
+            .ok_or_else(|| EngineError::new(&book_order.order, BookError::InvalidOrderType))?;
 
         let side = if is_aggressor {
             limit.side.opposite()
@@ -670,7 +1282,9 @@ where
                 let (base_quantity, quote_value) = order_quantity
                     .order
                     .get_quantity_and_value(executed_quantity, limit.price)
-                    .ok_or("Mathematical overflow")?;
+                    .ok_or_else(|| {
+                        EngineError::new(&order_quantity.order, EngineErrorKind::Overflow)
+                    })?;
 
                 match side {
                     Side::Ask => {
@@ -679,14 +1293,14 @@ where
                             order_quantity.order.clone(),
                             limit.price,
                             self.account_id,
-                            &self.margin_lot_event_handler,
+                            &self.pending_lot_events,
                         );
                         quote_asset_account.commit_receipt(
                             quote_value,
                             order_quantity.order.clone(),
                             limit.price,
                             self.account_id,
-                            &self.margin_lot_event_handler,
+                            &self.pending_lot_events,
                         );
                     }
                     Side::Bid => {
@@ -695,44 +1309,93 @@ where
                             order_quantity.order.clone(),
                             limit.price,
                             self.account_id,
-                            &self.margin_lot_event_handler,
+                            &self.pending_lot_events,
                         );
                         quote_asset_account.commit_delivery(
                             quote_value,
                             order_quantity.order.clone(),
                             limit.price,
                             self.account_id,
-                            &self.margin_lot_event_handler,
+                            &self.pending_lot_events,
                         );
                     }
                 };
 
                 Ok(())
             } else {
-                Err(format!(
-                    "Margin data not found for {}",
-                    order_quantity.order.market.quote_asset.symbol
-                )
-                .into())
+                Err(EngineError::new(
+                    &order_quantity.order,
+                    MarginError::AssetAccountNotFound(
+                        order_quantity.order.market.quote_asset.symbol.clone(),
+                    ),
+                ))
             }
         } else {
-            Err(format!(
-                "Margin data not found for {}",
-                order_quantity.order.market.base_asset.symbol
-            )
-            .into())
+            Err(EngineError::new(
+                &order_quantity.order,
+                MarginError::AssetAccountNotFound(
+                    order_quantity.order.market.base_asset.symbol.clone(),
+                ),
+            ))
         }
     }
 
-    /// Possibly support rollback
+    /// Finish transaction with other party, so that it can no longer be rolled
+    /// back, and send its lot events to event handler
+    pub fn execute_order_end(&mut self, order_quantity: &OrderQuantity) -> Result<(), EngineError> {
+        let (base_asset_account, quote_asset_account) =
+            self.get_market_asset_accounts(order_quantity)?;
+        base_asset_account.borrow_mut().end_journal();
+        quote_asset_account.borrow_mut().end_journal();
+        for event in self.pending_lot_events.0.take() {
+            event.send(&self.margin_lot_event_handler);
+        }
+        Ok(())
+    }
+
+    /// Start recording changes to accounts of order's market, so that they can
+    /// be undone like a transaction with other party
+    fn begin_order_journal(&self, order_quantity: &OrderQuantity) -> Result<(), EngineError> {
+        let (base_asset_account, quote_asset_account) =
+            self.get_market_asset_accounts(order_quantity)?;
+        base_asset_account.borrow_mut().begin_journal();
+        quote_asset_account.borrow_mut().begin_journal();
+        Ok(())
+    }
+
+    /// Undo everything done since transaction with other party began, and
+    /// drop its lot events
     pub fn execute_order_rollback(
         &mut self,
-        _executed_quantity: u64,
-        _order_quantity: &OrderQuantity,
-    ) -> Result<(), Box<dyn Error>> {
-        // TODO: Undo the the commit - What if rollback fails? ¯\_(ツ)_/¯
+        order_quantity: &OrderQuantity,
+    ) -> Result<(), EngineError> {
+        let (base_asset_account, quote_asset_account) =
+            self.get_market_asset_accounts(order_quantity)?;
+        base_asset_account.borrow_mut().rollback_journal();
+        quote_asset_account.borrow_mut().rollback_journal();
+        self.pending_lot_events.0.take();
         Ok(())
     }
+
+    /// Get accounts for base and quote assets of order's market
+    fn get_market_asset_accounts(
+        &self,
+        order_quantity: &OrderQuantity,
+    ) -> Result<(&SharedAssetAccount, &SharedAssetAccount), EngineError> {
+        let market = &order_quantity.order.market;
+        let get_asset_account = |symbol: &String| {
+            self.get_asset_account(symbol).ok_or_else(|| {
+                EngineError::new(
+                    &order_quantity.order,
+                    MarginError::AssetAccountNotFound(symbol.clone()),
+                )
+            })
+        };
+        Ok((
+            get_asset_account(&market.base_asset.symbol)?,
+            get_asset_account(&market.quote_asset.symbol)?,
+        ))
+    }
 }
 
 #[derive(Clone)]
@@ -773,25 +1436,31 @@ impl MarginLotEventHandler for MarginLotEventHandlerNull {
 
 /// Manager of all Margin accounts
 pub struct MarginManager<TLotHandler>
-where 
-    TLotHandler: MarginLotEventHandler + Clone
+where
+    TLotHandler: MarginLotEventHandler + Clone,
 {
     margins: HashMap<usize, Rc<RefCell<MarginTradingAccount<TLotHandler>>>>,
+    /// Initial margin of orders is checked only with margin model
+    margin_model: Option<MarginModel>,
     margin_lot_event_handler: TLotHandler,
 }
 
 impl<TLotHandler> MarginManager<TLotHandler>
-where 
-    TLotHandler: MarginLotEventHandler + Clone
+where
+    TLotHandler: MarginLotEventHandler + Clone,
 {
     pub fn new(margin_lot_event_handler: TLotHandler) -> Self {
         Self {
             margins: HashMap::new(),
+            margin_model: None,
             margin_lot_event_handler,
         }
     }
 
-    pub fn add_account(&mut self, participant_id: usize) -> &Rc<RefCell<MarginTradingAccount<TLotHandler>>> {
+    pub fn add_account(
+        &mut self,
+        participant_id: usize,
+    ) -> &Rc<RefCell<MarginTradingAccount<TLotHandler>>> {
         self.margins
             .entry(participant_id)
             .or_insert(Rc::new(RefCell::new(MarginTradingAccount::new(
@@ -800,46 +1469,118 @@ where
             ))))
     }
 
-    pub fn get_participants(&self) -> &HashMap<usize, Rc<RefCell<MarginTradingAccount<TLotHandler>>>> {
+    pub fn get_participants(
+        &self,
+    ) -> &HashMap<usize, Rc<RefCell<MarginTradingAccount<TLotHandler>>>> {
         &self.margins
     }
+
+    /// Check initial margin of orders against equity of accounts
+    ///
+    /// Balance policy of accounts still applies, so that accounts need margin
+    /// policy to borrow at all.
+    pub fn set_margin_model(&mut self, margin_model: MarginModel) -> &mut Self {
+        self.margin_model = Some(margin_model);
+        self
+    }
+
+    pub fn get_margin_model(&self) -> Option<&MarginModel> {
+        self.margin_model.as_ref()
+    }
+
+    pub fn get_margin_model_mut(&mut self) -> Option<&mut MarginModel> {
+        self.margin_model.as_mut()
+    }
+
+    /// Margin ratio of participant's account, see
+    /// [`MarginTradingAccount::get_margin_ratio`]
+    pub fn get_margin_ratio(&self, participant_id: usize) -> Result<Option<i128>, EngineError> {
+        let margin = self.margins.get(&participant_id).ok_or_else(|| {
+            EngineError::with_ids(participant_id, 0, MarginError::AccountNotFound)
+        })?;
+        let margin_model = self
+            .margin_model
+            .as_ref()
+            .ok_or_else(|| EngineError::with_ids(participant_id, 0, NotFoundError::MarginModel))?;
+        margin
+            .borrow()
+            .get_margin_ratio(margin_model)
+            .map_err(|err| EngineError::with_ids(participant_id, 0, err))
+    }
+
+    /// Initial margin of account before order, if there is margin model
+    fn get_initial_margin_before(
+        &self,
+        margin: &MarginTradingAccount<TLotHandler>,
+        order: &Order,
+    ) -> Result<Option<i128>, EngineError> {
+        self.margin_model
+            .as_ref()
+            .map(|margin_model| margin.get_initial_margin(margin_model))
+            .transpose()
+            .map_err(|err| EngineError::new(order, err))
+    }
 }
 
 impl<TLotHandler> ExecutionPolicy for MarginManager<TLotHandler>
-where 
-    TLotHandler: MarginLotEventHandler + Clone
+where
+    TLotHandler: MarginLotEventHandler + Clone,
 {
     /// Perform margin checks and accounting for new order placement
-    fn place_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), Box<dyn Error>> {
-        if order_quantity.quantity > 0 {
+    fn place_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), EngineError> {
+        if order_quantity.get_total_quantity() > 0 {
             if let Some(margin) = self.margins.get(&order_quantity.order.participant_id) {
-                margin.borrow_mut().place_order(order_quantity)
+                let mut margin = margin.borrow_mut();
+                margin.check_reduce_only(
+                    &order_quantity.order,
+                    order_quantity.get_total_quantity(),
+                )?;
+                let initial_margin_before =
+                    self.get_initial_margin_before(&margin, &order_quantity.order)?;
+                margin.place_order(order_quantity)?;
+                if let (Some(margin_model), Some(initial_margin_before)) =
+                    (&self.margin_model, initial_margin_before)
+                {
+                    if let Err(err) = margin.check_initial_margin(
+                        margin_model,
+                        &order_quantity.order,
+                        initial_margin_before,
+                    ) {
+                        margin.cancel_order(order_quantity)?;
+                        return Err(err);
+                    }
+                }
+                Ok(())
             } else {
-                Err(format!(
-                    "Margin not found for {}",
-                    order_quantity.order.participant_id
-                )
-                .into())
+                Err(EngineError::new(
+                    &order_quantity.order,
+                    MarginError::AccountNotFound,
+                ))
             }
         } else {
-            Err("Not enough quantity".into())
+            Err(EngineError::new(
+                &order_quantity.order,
+                BookError::NotEnoughQuantity,
+            ))
         }
     }
 
     /// Perform margin checks and accounting for order cancel
-    fn cancel_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), Box<dyn Error>> {
-        if order_quantity.quantity > 0 {
+    fn cancel_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), EngineError> {
+        if order_quantity.get_total_quantity() > 0 {
             if let Some(margin) = self.margins.get(&order_quantity.order.participant_id) {
                 margin.borrow_mut().cancel_order(order_quantity)
             } else {
-                Err(format!(
-                    "Margin not found for {}",
-                    order_quantity.order.participant_id
-                )
-                .into())
+                Err(EngineError::new(
+                    &order_quantity.order,
+                    MarginError::AccountNotFound,
+                ))
             }
         } else {
-            Err("Not enough quantity".into())
+            Err(EngineError::new(
+                &order_quantity.order,
+                BookError::NotEnoughQuantity,
+            ))
         }
     }
 
@@ -849,96 +1590,449 @@ where
         executed_quantity: &mut u64,
         aggressor_order: &mut OrderQuantity,
         book_order: &mut OrderQuantity,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), EngineError> {
         if *executed_quantity == 0 {
-            return Err("Not enough quantity".into());
+            return Err(EngineError::new(
+                &aggressor_order.order,
+                BookError::NotEnoughQuantity,
+            ));
         }
-        if aggressor_order.order.participant_id == book_order.order.participant_id {
-            return Err("Self-trade not possible".into());
+        let aggressor_margin = self
+            .margins
+            .get(&aggressor_order.order.participant_id)
+            .ok_or_else(|| {
+                EngineError::new(&aggressor_order.order, MarginError::AccountNotFound)
+            })?;
+        let book_margin = self
+            .margins
+            .get(&book_order.order.participant_id)
+            .ok_or_else(|| EngineError::new(&book_order.order, MarginError::AccountNotFound))?;
+        // Reduce-only orders are checked against position committed so far
+        aggressor_margin
+            .borrow()
+            .check_reduce_only(&aggressor_order.order, *executed_quantity)?;
+        book_margin
+            .borrow()
+            .check_reduce_only(&book_order.order, *executed_quantity)?;
+        let mut aggressor_margin_mut = aggressor_margin.borrow_mut();
+        let mut book_margin_mut = book_margin.borrow_mut();
+
+        // Insufficient balance is told as is, so that participant knows why
+        let begin_failed = |order: &Order, err: EngineError| match err.kind {
+            EngineErrorKind::Margin(MarginError::InsufficientBalance(_)) => err,
+            _ => EngineError::new(order, MarginError::ExecutionBeginFailed),
+        };
+        // Book order was checked for initial margin when it was placed
+        let initial_margin_before =
+            self.get_initial_margin_before(&aggressor_margin_mut, &aggressor_order.order)?;
+        if let Err(err) = aggressor_margin_mut.execute_order_begin(
+            executed_quantity,
+            aggressor_order,
+            book_order,
+            true,
+        ) {
+            aggressor_margin_mut.execute_order_rollback(aggressor_order)?;
+            return Err(begin_failed(&aggressor_order.order, err));
         }
-
-        let result = if let Some(aggressor_margin) =
-            self.margins.get(&aggressor_order.order.participant_id)
+        if let (Some(margin_model), Some(initial_margin_before)) =
+            (&self.margin_model, initial_margin_before)
         {
+            if let Err(err) = aggressor_margin_mut.check_initial_margin(
+                margin_model,
+                &aggressor_order.order,
+                initial_margin_before,
+            ) {
+                aggressor_margin_mut.execute_order_rollback(aggressor_order)?;
+                return Err(err);
+            }
+        }
+        if let Err(err) =
+            book_margin_mut.execute_order_begin(executed_quantity, book_order, book_order, false)
+        {
+            aggressor_margin_mut.execute_order_rollback(aggressor_order)?;
+            book_margin_mut.execute_order_rollback(book_order)?;
+            return Err(begin_failed(&book_order.order, err));
+        }
+
+        let commit_result = aggressor_margin_mut
+            .execute_order_commit(*executed_quantity, aggressor_order, book_order, true)
+            .map_err(|_| {
+                EngineError::new(&aggressor_order.order, MarginError::ExecutionCommitFailed)
+            })
+            .and_then(|_| {
+                book_margin_mut
+                    .execute_order_commit(*executed_quantity, book_order, book_order, false)
+                    .map_err(|_| {
+                        EngineError::new(&book_order.order, MarginError::ExecutionCommitFailed)
+                    })
+            });
+        if let Err(err) = commit_result {
+            aggressor_margin_mut.execute_order_rollback(aggressor_order)?;
+            book_margin_mut.execute_order_rollback(book_order)?;
+            return Err(err);
+        }
+
+        aggressor_margin_mut.execute_order_end(aggressor_order)?;
+        book_margin_mut.execute_order_end(book_order)?;
+        aggressor_order.quantity -= *executed_quantity;
+        book_order.quantity -= *executed_quantity;
+        Ok(())
+    }
+
+    /// Begin all executions in turn, as execution of orders would, and roll
+    /// them all back
+    fn check_executions(
+        &self,
+        aggressor_order: &OrderQuantity,
+        executions: &[(u64, OrderQuantity)],
+    ) -> Result<(), EngineError> {
+        let get_margin = |order: &Order| {
+            self.margins
+                .get(&order.participant_id)
+                .ok_or_else(|| EngineError::new(order, MarginError::AccountNotFound))
+        };
+        let aggressor_margin = get_margin(&aggressor_order.order)?;
+        let begin_failed = |order: &Order, err: EngineError| match err.kind {
+            EngineErrorKind::Margin(MarginError::InsufficientBalance(_)) => err,
+            _ => EngineError::new(order, MarginError::ExecutionBeginFailed),
+        };
+        let executed_quantity = executions.iter().map(|(quantity, _)| quantity).sum();
+        aggressor_margin
+            .borrow()
+            .check_reduce_only(&aggressor_order.order, executed_quantity)?;
+        let initial_margin_before =
+            self.get_initial_margin_before(&aggressor_margin.borrow(), &aggressor_order.order)?;
+        let mut result = Ok(());
+        let mut begun_orders = Vec::new();
+        for (executed_quantity, book_order) in executions {
+            let mut executed_quantity = *executed_quantity;
+            let book_margin = match get_margin(&book_order.order).and_then(|book_margin| {
+                book_margin
+                    .borrow()
+                    .check_reduce_only(&book_order.order, executed_quantity)?;
+                Ok(book_margin)
+            }) {
+                Ok(book_margin) => book_margin,
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            };
             let mut aggressor_margin_mut = aggressor_margin.borrow_mut();
-            if let Ok(()) = aggressor_margin_mut.execute_order_begin(
-                executed_quantity,
+            if let Err(err) = aggressor_margin_mut.execute_order_begin(
+                &mut executed_quantity,
                 aggressor_order,
-                &book_order,
+                book_order,
                 true,
             ) {
-                if let Some(book_margin) = self.margins.get(&book_order.order.participant_id) {
-                    let mut book_margin_mut = book_margin.borrow_mut();
-                    if let Ok(()) = book_margin_mut.execute_order_begin(
-                        executed_quantity,
-                        book_order,
-                        &book_order,
-                        false,
-                    ) {
-                        if let Ok(()) = aggressor_margin_mut.execute_order_commit(
-                            *executed_quantity,
-                            &aggressor_order,
-                            &book_order,
-                            true,
-                        ) {
-                            if let Ok(()) = book_margin_mut.execute_order_commit(
-                                *executed_quantity,
-                                &book_order,
-                                &book_order,
-                                false,
-                            ) {
-                                Ok(())
-                            } else {
-                                if let Err(err) = aggressor_margin_mut
-                                    .execute_order_rollback(*executed_quantity, &aggressor_order)
-                                {
-                                    Err(err)
-                                } else {
-                                    Err(format!(
-                                        "Margin failed commit execution for {}",
-                                        book_order.order.participant_id
-                                    )
-                                    .into())
-                                }
-                            }
-                        } else {
-                            Err(format!(
-                                "Margin failed commit execute for {}",
-                                book_order.order.participant_id
-                            )
-                            .into())
-                        }
-                    } else {
-                        Err(format!(
-                            "Margin failed begin execute for {}",
-                            book_order.order.participant_id
-                        )
-                        .into())
-                    }
-                } else {
-                    Err(format!("Margin not found for {}", book_order.order.participant_id).into())
+                result = Err(begin_failed(&aggressor_order.order, err));
+                break;
+            }
+            if let (Some(margin_model), Some(initial_margin_before)) =
+                (&self.margin_model, initial_margin_before)
+            {
+                if let Err(err) = aggressor_margin_mut.check_initial_margin(
+                    margin_model,
+                    &aggressor_order.order,
+                    initial_margin_before,
+                ) {
+                    result = Err(err);
+                    break;
                 }
-            } else {
-                Err(format!(
-                    "Margin failed begin execute for {}",
-                    aggressor_order.order.participant_id
-                )
-                .into())
             }
-        } else {
-            Err(format!(
-                "Margin not found for {}",
-                aggressor_order.order.participant_id
+            drop(aggressor_margin_mut);
+            begun_orders.push((book_margin, book_order));
+            if let Err(err) = book_margin.borrow_mut().execute_order_begin(
+                &mut executed_quantity,
+                book_order,
+                book_order,
+                false,
+            ) {
+                result = Err(begin_failed(&book_order.order, err));
+                break;
+            }
+        }
+        aggressor_margin
+            .borrow_mut()
+            .execute_order_rollback(aggressor_order)?;
+        for (book_margin, book_order) in begun_orders {
+            book_margin
+                .borrow_mut()
+                .execute_order_rollback(book_order)?;
+        }
+        result
+    }
+
+    /// Cancel promise of replaced order and place the order in its place, as
+    /// amending the order would, and roll both back
+    fn check_replace_order(
+        &self,
+        replaced_order: &OrderQuantity,
+        order_quantity: &OrderQuantity,
+    ) -> Result<(), EngineError> {
+        let margin = self
+            .margins
+            .get(&order_quantity.order.participant_id)
+            .ok_or_else(|| EngineError::new(&order_quantity.order, MarginError::AccountNotFound))?;
+        margin.borrow().begin_order_journal(order_quantity)?;
+        let result = self
+            .cancel_order(&mut replaced_order.clone())
+            .and_then(|_| self.place_order(&mut order_quantity.clone()));
+        margin.borrow_mut().execute_order_rollback(order_quantity)?;
+        result
+    }
+}
+
+/// Deposit quantity of an asset, in decimals of the asset
+#[cfg(test)]
+pub fn test_deposit<TLotHandler: MarginLotEventHandler>(
+    margin: &mut MarginTradingAccount<TLotHandler>,
+    asset: &Rc<Asset>,
+    quantity: u64,
+) {
+    let market = Rc::new(Market {
+        symbol: asset.symbol.clone(),
+        base_asset: asset.clone(),
+        base_decimals: asset.decimals,
+        ..Rc::into_inner(test_market()).unwrap()
+    });
+    let participant_id = margin.account_id;
+    margin
+        .transfer(
+            Rc::new(test_order(
+                &market,
+                participant_id,
+                0,
+                OrderType::Deposit(quantity),
+            )),
+            0,
+        )
+        .unwrap();
+}
+
+#[cfg(test)]
+type MarginLotSnapshot = (u64, u64, Vec<(usize, usize, u64, u64)>);
+
+#[cfg(test)]
+type MarginSideSnapshot = (u64, u64, u64, Vec<MarginLotSnapshot>);
+
+#[cfg(test)]
+type LotEventSnapshot = (&'static str, String, Side, u64, u64);
+
+/// Lot events told to event handler, with lot's original and left quantity
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct RecordLotEvents(Rc<RefCell<Vec<LotEventSnapshot>>>);
+
+#[cfg(test)]
+impl MarginLotEventHandler for RecordLotEvents {
+    fn handle_lot_opened(
+        &self,
+        asset: Rc<Asset>,
+        side: Side,
+        lot: &MarginLot,
+        _order: Rc<Order>,
+        _price: u64,
+        _account_id: usize,
+    ) {
+        let event = (
+            "opened",
+            asset.symbol.clone(),
+            side,
+            lot.quantity_orig,
+            lot.quantity_left,
+        );
+        self.0.borrow_mut().push(event);
+    }
+    fn handle_lot_updated(
+        &self,
+        asset: Rc<Asset>,
+        side: Side,
+        lot: &MarginLot,
+        _order: Rc<Order>,
+        _price: u64,
+        _account_id: usize,
+    ) {
+        let event = (
+            "updated",
+            asset.symbol.clone(),
+            side,
+            lot.quantity_orig,
+            lot.quantity_left,
+        );
+        self.0.borrow_mut().push(event);
+    }
+    fn handle_lot_closed(
+        &self,
+        asset: Rc<Asset>,
+        side: Side,
+        lot: MarginLot,
+        _order: Rc<Order>,
+        _price: u64,
+        _account_id: usize,
+    ) {
+        let event = (
+            "closed",
+            asset.symbol.clone(),
+            side,
+            lot.quantity_orig,
+            lot.quantity_left,
+        );
+        self.0.borrow_mut().push(event);
+    }
+}
+
+/// Capture quantities and lots of both sides of every asset account
+#[cfg(test)]
+fn snapshot_margin<TLotHandler: MarginLotEventHandler>(
+    margin: &MarginTradingAccount<TLotHandler>,
+) -> Vec<(String, MarginSideSnapshot, MarginSideSnapshot)> {
+    let snapshot_side = |side: &MarginSide| {
+        let lots = side
+            .open_lots
+            .iter()
+            .map(|lot| {
+                let transactions = lot
+                    .transactions
+                    .iter()
+                    .map(|t| {
+                        (
+                            t.order.participant_id,
+                            t.order.order_id,
+                            t.executed_price,
+                            t.executed_quantity,
+                        )
+                    })
+                    .collect();
+                (lot.quantity_orig, lot.quantity_left, transactions)
+            })
+            .collect();
+        (
+            side.quantity_open,
+            side.quantity_locked,
+            side.quantity_committed,
+            lots,
+        )
+    };
+    margin
+        .portfolio
+        .iter()
+        .sorted_by_key(|(symbol, _)| *symbol)
+        .map(|(symbol, account)| {
+            let account = account.borrow();
+            (
+                symbol.clone(),
+                snapshot_side(&account.received),
+                snapshot_side(&account.delivered),
             )
-            .into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_execute_order_rollback(
+        executions in proptest::collection::vec(
+            (proptest::bool::ANY, 1..100u64, 1..1000u64, 1..1000u64, proptest::bool::ANY),
+            0..40,
+        )
+    ) {
+        let market = test_market();
+        let new_margin = |participant_id| {
+            let mut margin = MarginTradingAccount::new(participant_id, RecordLotEvents::default());
+            margin
+                .add_asset_account(&market.base_asset)
+                .add_asset_account(&market.quote_asset);
+            test_deposit(&mut margin, &market.base_asset, 1_000_000_000_000);
+            test_deposit(&mut margin, &market.quote_asset, 1_000_000_000_000);
+            margin
         };
+        // Margins with every execution, and margins with only those not rolled back
+        let mut rolled_back = (new_margin(1001), new_margin(1002));
+        let mut committed_only = (new_margin(1001), new_margin(1002));
 
-        if let Err(err) = result {
-            Err(err)
-        } else {
-            aggressor_order.quantity -= *executed_quantity;
-            book_order.quantity += *executed_quantity;
-            Ok(())
+        for (order_id, (is_bid, price, quantity, executed_quantity, rollback)) in
+            executions.into_iter().enumerate()
+        {
+            let side = if is_bid { Side::Bid } else { Side::Ask };
+            let executed_quantity = executed_quantity.min(quantity);
+            let aggressor_order = OrderQuantity {
+                order: test_limit_order(&market, 1001, order_id, side, price, quantity),
+                quantity,
+                hidden_quantity: 0,
+            };
+            let mut book_order = OrderQuantity {
+                order: test_limit_order(&market, 1002, order_id, side.opposite(), price, quantity),
+                quantity,
+                hidden_quantity: 0,
+            };
+
+            for (_, book_margin) in [&mut rolled_back, &mut committed_only] {
+                book_margin.place_order(&mut book_order).unwrap();
+            }
+            let margins = if rollback {
+                &mut rolled_back
+            } else {
+                &mut committed_only
+            };
+            let before = (snapshot_margin(&margins.0), snapshot_margin(&margins.1));
+            for (margin, order, is_aggressor) in [
+                (&mut margins.0, &aggressor_order, true),
+                (&mut margins.1, &book_order, false),
+            ] {
+                margin
+                    .execute_order_begin(&mut executed_quantity.clone(), order, &book_order, is_aggressor)
+                    .unwrap();
+            }
+            for (margin, order, is_aggressor) in [
+                (&mut margins.0, &aggressor_order, true),
+                (&mut margins.1, &book_order, false),
+            ] {
+                margin
+                    .execute_order_commit(executed_quantity, order, &book_order, is_aggressor)
+                    .unwrap();
+            }
+            if rollback {
+                margins.0.execute_order_rollback(&aggressor_order).unwrap();
+                margins.1.execute_order_rollback(&book_order).unwrap();
+                proptest::prop_assert!(
+                    before == (snapshot_margin(&margins.0), snapshot_margin(&margins.1))
+                );
+            } else {
+                margins.0.execute_order_end(&aggressor_order).unwrap();
+                margins.1.execute_order_end(&book_order).unwrap();
+                // Same execution happens in both, as if none was ever rolled back
+                for (margin, order, is_aggressor) in [
+                    (&mut rolled_back.0, &aggressor_order, true),
+                    (&mut rolled_back.1, &book_order, false),
+                ] {
+                    margin
+                        .execute_order_begin(&mut executed_quantity.clone(), order, &book_order, is_aggressor)
+                        .unwrap();
+                    margin
+                        .execute_order_commit(executed_quantity, order, &book_order, is_aggressor)
+                        .unwrap();
+                    margin.execute_order_end(order).unwrap();
+                }
+            }
+            book_order.quantity -= executed_quantity;
+            for (_, book_margin) in [&mut rolled_back, &mut committed_only] {
+                book_margin.cancel_order(&mut book_order).unwrap();
+            }
         }
+
+        proptest::prop_assert!(
+            (snapshot_margin(&rolled_back.0), snapshot_margin(&rolled_back.1))
+                == (snapshot_margin(&committed_only.0), snapshot_margin(&committed_only.1))
+        );
+        // Lot events of rolled back executions were never told
+        let get_events = |margin: &MarginTradingAccount<RecordLotEvents>| {
+            margin.margin_lot_event_handler.0.borrow().clone()
+        };
+        proptest::prop_assert!(
+            (get_events(&rolled_back.0), get_events(&rolled_back.1))
+                == (get_events(&committed_only.0), get_events(&committed_only.1))
+        );
     }
 }
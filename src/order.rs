@@ -1,6 +1,6 @@
-use std::{fmt, rc::Rc};
+use std::{cmp::min, error::Error, fmt, rc::Rc};
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Side {
     Bid,
     Ask,
@@ -20,6 +20,31 @@ pub struct Asset {
     pub decimals: u8,
 }
 
+/// Band around the best price at arrival of market order, beyond which it does not match
+#[derive(Clone, Copy)]
+pub enum PriceProtection {
+    /// Percentage of the best price in basis points (1/100 of percent)
+    BasisPoints(u64),
+    /// Number of market ticks
+    Ticks(u64),
+}
+
+impl PriceProtection {
+    /// Tell the worst price order on given side may match at
+    pub fn get_protection_price(&self, side: Side, best_price: u64, tick: u64) -> Option<u64> {
+        let band = match self {
+            PriceProtection::BasisPoints(basis_points) => {
+                best_price.checked_mul(*basis_points)? / 10000
+            }
+            PriceProtection::Ticks(ticks) => ticks.checked_mul(tick)?,
+        };
+        match side {
+            Side::Bid => best_price.checked_add(band),
+            Side::Ask => Some(best_price.saturating_sub(band)),
+        }
+    }
+}
+
 pub struct Market {
     pub symbol: String,
     pub base_asset: Rc<Asset>,
@@ -28,6 +53,96 @@ pub struct Market {
     pub multiplier: u16,
     pub base_decimals: u8,
     pub quote_decimals: u8,
+    /// Slippage collar for market orders, or `None` to sweep the whole book
+    pub price_protection: Option<PriceProtection>,
+    pub order_limits: OrderLimits,
+}
+
+/// Limits of order size, in base decimals (quantity) and quote decimals (notional)
+#[derive(Default)]
+pub struct OrderLimits {
+    pub min_quantity: Option<u64>,
+    pub max_quantity: Option<u64>,
+    pub min_notional: Option<u64>,
+    pub max_notional: Option<u64>,
+}
+
+/// Reason why order was rejected by validation against its market
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderRejectReason {
+    /// Price (or trigger price) is zero or not a multiple of market tick
+    InvalidPrice,
+    /// Quantity is not a multiple of market lot size (multiplier)
+    InvalidLotSize,
+    QuantityBelowMinimum,
+    QuantityAboveMaximum,
+    NotionalBelowMinimum,
+    NotionalAboveMaximum,
+    /// Display quantity of iceberg order is zero, or above its total quantity
+    InvalidDisplayQuantity,
+}
+
+impl fmt::Display for OrderRejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Self::InvalidPrice => "Price is not a multiple of tick",
+            Self::InvalidLotSize => "Quantity is not a multiple of lot size",
+            Self::QuantityBelowMinimum => "Quantity is below minimum",
+            Self::QuantityAboveMaximum => "Quantity is above maximum",
+            Self::NotionalBelowMinimum => "Notional is below minimum",
+            Self::NotionalAboveMaximum => "Notional is above maximum",
+            Self::InvalidDisplayQuantity => "Display quantity is zero or above quantity",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+impl Error for OrderRejectReason {}
+
+impl Market {
+    pub fn validate_price(&self, price: u64) -> Result<(), OrderRejectReason> {
+        if price == 0 || price.checked_rem(self.tick).is_some_and(|rem| rem != 0) {
+            return Err(OrderRejectReason::InvalidPrice);
+        }
+        Ok(())
+    }
+
+    pub fn validate_quantity(&self, quantity: u64) -> Result<(), OrderRejectReason> {
+        let limits = &self.order_limits;
+        if quantity == 0 || limits.min_quantity.is_some_and(|min| quantity < min) {
+            return Err(OrderRejectReason::QuantityBelowMinimum);
+        }
+        if limits.max_quantity.is_some_and(|max| max < quantity) {
+            return Err(OrderRejectReason::QuantityAboveMaximum);
+        }
+        if quantity
+            .checked_rem(self.multiplier as u64)
+            .is_some_and(|rem| rem != 0)
+        {
+            return Err(OrderRejectReason::InvalidLotSize);
+        }
+        Ok(())
+    }
+
+    pub fn validate_notional(&self, notional: u64) -> Result<(), OrderRejectReason> {
+        let limits = &self.order_limits;
+        if limits.min_notional.is_some_and(|min| notional < min) {
+            return Err(OrderRejectReason::NotionalBelowMinimum);
+        }
+        if limits.max_notional.is_some_and(|max| max < notional) {
+            return Err(OrderRejectReason::NotionalAboveMaximum);
+        }
+        Ok(())
+    }
+
+    /// Validate price, quantity, and value of limit order
+    pub fn validate_limit(&self, price: u64, quantity: u64) -> Result<(), OrderRejectReason> {
+        self.validate_price(price)?;
+        self.validate_quantity(quantity)?;
+        let notional = calculate_value(quantity, price, self.base_decimals, self.quote_decimals)
+            .ok_or(OrderRejectReason::NotionalAboveMaximum)?;
+        self.validate_notional(notional)
+    }
 }
 
 pub struct LimitOrder {
@@ -41,18 +156,87 @@ pub struct MarketOrder {
     pub quantity: u64,
 }
 
+/// Market order sized by value in quote currency instead of base quantity
+pub struct QuoteMarketOrder {
+    pub side: Side,
+    /// Value to spend (buy) or receive (sell) in quote decimals
+    pub value: u64,
+}
+
+/// Market order placed once last trade price reaches trigger price
+pub struct StopOrder {
+    pub side: Side,
+    pub trigger_price: u64,
+    pub quantity: u64,
+}
+
+/// Limit order placed once last trade price reaches trigger price
+pub struct StopLimitOrder {
+    pub side: Side,
+    pub trigger_price: u64,
+    pub price: u64,
+    pub quantity: u64,
+}
+
+/// Limit order, which is never matched as an aggressor
+pub struct PostOnlyOrder {
+    pub limit: LimitOrder,
+    /// Re-price one tick away from the opposite side instead of rejecting
+    pub reprice: bool,
+}
+
+/// Limit order showing only part of its quantity on the book at a time
+pub struct IcebergOrder {
+    pub limit: LimitOrder,
+    pub display_quantity: u64,
+}
+
+/// What to do when aggressor order would match resting order of the same participant
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTradePrevention {
+    /// Cancel the rest of aggressor order
+    #[default]
+    CancelNewest,
+    /// Cancel resting order and continue matching
+    CancelOldest,
+    /// Cancel both resting order and the rest of aggressor order
+    CancelBoth,
+    /// Decrease both orders by the smaller quantity, and cancel the one left with none
+    DecrementAndCancel,
+}
+
 pub enum OrderType {
     Deposit(u64),
     Withdraw(u64),
     ImmediateOrCancel(LimitOrder),
+    FillOrKill(LimitOrder),
     Limit(LimitOrder),
-    Market(MarketOrder), // TODO: Add OCO and Stop orders
+    PostOnly(PostOnlyOrder),
+    Iceberg(IcebergOrder),
+    Market(MarketOrder),
+    QuoteMarket(QuoteMarketOrder),
+    Stop(StopOrder),
+    StopLimit(StopLimitOrder),
 }
 
 pub struct Order {
     pub market: Rc<Market>,
     pub participant_id: usize,
+    /// Exchange order id, unique among open orders of participant
     pub order_id: usize,
+    /// Order id chosen by participant, unique among its open and recent orders
+    pub client_order_id: Option<String>,
+    /// Time (see [`Clock`](crate::clock::Clock)) at which order is cancelled if
+    /// still open, i.e. Good-Till-Date/Good-Till-Time, or Good-Till-Cancel if `None`
+    pub expire_time: Option<u64>,
+    /// Self-trade prevention mode, or `None` for the mode of the participant
+    pub self_trade_prevention: Option<SelfTradePrevention>,
+    /// Cancel order when participant misses heartbeats, if it asked to cancel
+    /// only flagged orders
+    pub cancel_on_disconnect: bool,
+    /// Order may only reduce position of participant in base asset of market,
+    /// and never turn it to the other side
+    pub reduce_only: bool,
     pub order_data: OrderType,
 }
 
@@ -79,6 +263,159 @@ impl Order {
 
         Some((order_quantity_changed, order_value_changed))
     }
+
+    /// Validate order against tick, lot size, and order limits of its market
+    pub fn validate(&self) -> Result<(), OrderRejectReason> {
+        let market = &self.market;
+        match &self.order_data {
+            OrderType::Limit(limit)
+            | OrderType::ImmediateOrCancel(limit)
+            | OrderType::FillOrKill(limit) => market.validate_limit(limit.price, limit.quantity),
+            OrderType::PostOnly(post_only) => {
+                market.validate_limit(post_only.limit.price, post_only.limit.quantity)
+            }
+            OrderType::Iceberg(iceberg) => {
+                market.validate_limit(iceberg.limit.price, iceberg.limit.quantity)?;
+                if iceberg.display_quantity == 0
+                    || iceberg.limit.quantity < iceberg.display_quantity
+                {
+                    return Err(OrderRejectReason::InvalidDisplayQuantity);
+                }
+                market.validate_quantity(iceberg.display_quantity)
+            }
+            OrderType::Market(market_order) => market.validate_quantity(market_order.quantity),
+            OrderType::QuoteMarket(quote_market) => market.validate_notional(quote_market.value),
+            OrderType::Stop(stop) => {
+                market.validate_price(stop.trigger_price)?;
+                market.validate_quantity(stop.quantity)
+            }
+            OrderType::StopLimit(stop) => {
+                market.validate_price(stop.trigger_price)?;
+                market.validate_limit(stop.price, stop.quantity)
+            }
+            OrderType::Deposit(_) | OrderType::Withdraw(_) => Ok(()),
+        }
+    }
+
+    /// Base quantity of order, unless it is sized otherwise
+    pub fn get_quantity(&self) -> Option<u64> {
+        match &self.order_data {
+            OrderType::Limit(limit)
+            | OrderType::ImmediateOrCancel(limit)
+            | OrderType::FillOrKill(limit) => Some(limit.quantity),
+            OrderType::PostOnly(post_only) => Some(post_only.limit.quantity),
+            OrderType::Iceberg(iceberg) => Some(iceberg.limit.quantity),
+            OrderType::Market(market_order) => Some(market_order.quantity),
+            OrderType::Stop(stop) => Some(stop.quantity),
+            OrderType::StopLimit(stop) => Some(stop.quantity),
+            OrderType::Deposit(quantity) | OrderType::Withdraw(quantity) => Some(*quantity),
+            OrderType::QuoteMarket(_) => None,
+        }
+    }
+
+    /// Side of trading order
+    pub fn get_side(&self) -> Option<Side> {
+        match &self.order_data {
+            OrderType::Limit(limit)
+            | OrderType::ImmediateOrCancel(limit)
+            | OrderType::FillOrKill(limit) => Some(limit.side),
+            OrderType::PostOnly(post_only) => Some(post_only.limit.side),
+            OrderType::Iceberg(iceberg) => Some(iceberg.limit.side),
+            OrderType::Market(market_order) => Some(market_order.side),
+            OrderType::QuoteMarket(quote_market) => Some(quote_market.side),
+            OrderType::Stop(stop) => Some(stop.side),
+            OrderType::StopLimit(stop) => Some(stop.side),
+            OrderType::Deposit(_) | OrderType::Withdraw(_) => None,
+        }
+    }
+
+    /// Tell if order never rests on the book, and so it is done once placed
+    pub fn is_immediate(&self) -> bool {
+        matches!(
+            self.order_data,
+            OrderType::ImmediateOrCancel(_)
+                | OrderType::FillOrKill(_)
+                | OrderType::Market(_)
+                | OrderType::QuoteMarket(_)
+        )
+    }
+
+    /// Limit of an order that can rest on the book
+    pub fn get_book_limit(&self) -> Option<&LimitOrder> {
+        match &self.order_data {
+            OrderType::Limit(limit) => Some(limit),
+            OrderType::PostOnly(post_only) => Some(&post_only.limit),
+            OrderType::Iceberg(iceberg) => Some(&iceberg.limit),
+            _ => None,
+        }
+    }
+
+    /// Same order resting on the book at different price and/or quantity
+    pub fn new_amended_order(&self, price: u64, quantity: u64) -> Option<Self> {
+        let order_data = match &self.order_data {
+            OrderType::Limit(limit) => OrderType::Limit(LimitOrder {
+                side: limit.side,
+                price,
+                quantity,
+            }),
+            OrderType::PostOnly(post_only) => OrderType::PostOnly(PostOnlyOrder {
+                limit: LimitOrder {
+                    side: post_only.limit.side,
+                    price,
+                    quantity,
+                },
+                reprice: post_only.reprice,
+            }),
+            OrderType::Iceberg(iceberg) => OrderType::Iceberg(IcebergOrder {
+                limit: LimitOrder {
+                    side: iceberg.limit.side,
+                    price,
+                    quantity,
+                },
+                // Reduced order cannot display more than its quantity
+                display_quantity: min(iceberg.display_quantity, quantity),
+            }),
+            _ => return None,
+        };
+        Some(Self {
+            market: self.market.clone(),
+            participant_id: self.participant_id,
+            order_id: self.order_id,
+            client_order_id: self.client_order_id.clone(),
+            expire_time: self.expire_time,
+            self_trade_prevention: self.self_trade_prevention,
+            cancel_on_disconnect: self.cancel_on_disconnect,
+            reduce_only: self.reduce_only,
+            order_data,
+        })
+    }
+
+    /// Order to be placed when stop order is triggered
+    pub fn new_triggered_order(&self) -> Option<Self> {
+        let order_data = match &self.order_data {
+            OrderType::Stop(stop) => OrderType::Market(MarketOrder {
+                side: stop.side,
+                quantity: stop.quantity,
+            }),
+            OrderType::StopLimit(stop) => OrderType::Limit(LimitOrder {
+                side: stop.side,
+                price: stop.price,
+                quantity: stop.quantity,
+            }),
+            _ => return None,
+        };
+        Some(Self {
+            market: self.market.clone(),
+            participant_id: self.participant_id,
+            order_id: self.order_id,
+            client_order_id: self.client_order_id.clone(),
+            expire_time: self.expire_time,
+            self_trade_prevention: self.self_trade_prevention,
+            cancel_on_disconnect: self.cancel_on_disconnect,
+            reduce_only: self.reduce_only,
+            order_data,
+        })
+    }
 }
 
 pub fn side_name(side: Side) -> &'static str {
@@ -190,6 +527,23 @@ impl fmt::Display for Order {
                 quote_price_fmt(limit.price, &self.market)
             ),
 
+            OrderType::PostOnly(post_only) => write!(
+                f,
+                "PostOnly {} {} @ {}",
+                side_name(post_only.limit.side),
+                base_quantity_fmt(post_only.limit.quantity, &self.market),
+                quote_price_fmt(post_only.limit.price, &self.market)
+            ),
+
+            OrderType::Iceberg(iceberg) => write!(
+                f,
+                "Iceberg {} {} ({}) @ {}",
+                side_name(iceberg.limit.side),
+                base_quantity_fmt(iceberg.limit.quantity, &self.market),
+                base_quantity_fmt(iceberg.display_quantity, &self.market),
+                quote_price_fmt(iceberg.limit.price, &self.market)
+            ),
+
             OrderType::ImmediateOrCancel(limit) => write!(
                 f,
                 "IOC {} {} @ {}",
@@ -198,12 +552,43 @@ impl fmt::Display for Order {
                 quote_price_fmt(limit.price, &self.market)
             ),
 
+            OrderType::FillOrKill(limit) => write!(
+                f,
+                "FOK {} {} @ {}",
+                side_name(limit.side),
+                base_quantity_fmt(limit.quantity, &self.market),
+                quote_price_fmt(limit.price, &self.market)
+            ),
+
             OrderType::Market(market_order) => write!(
                 f,
                 "Market {} {}",
                 side_name(market_order.side),
                 base_quantity_fmt(market_order.quantity, &self.market)
             ),
+            OrderType::QuoteMarket(quote_market) => write!(
+                f,
+                "Market {} {}",
+                side_name(quote_market.side),
+                quote_price_fmt(quote_market.value, &self.market)
+            ),
+            OrderType::Stop(stop) => write!(
+                f,
+                "Stop {} {} trigger {}",
+                side_name(stop.side),
+                base_quantity_fmt(stop.quantity, &self.market),
+                quote_price_fmt(stop.trigger_price, &self.market)
+            ),
+
+            OrderType::StopLimit(stop) => write!(
+                f,
+                "StopLimit {} {} @ {} trigger {}",
+                side_name(stop.side),
+                base_quantity_fmt(stop.quantity, &self.market),
+                quote_price_fmt(stop.price, &self.market),
+                quote_price_fmt(stop.trigger_price, &self.market)
+            ),
+
             OrderType::Deposit(quantity) => {
                 write!(f, "Deposit {}", base_quantity_fmt(*quantity, &self.market))
             }
@@ -283,3 +668,66 @@ fn test_calculate_value_2() {
     );
     assert_eq!(value_changed, 6250000);
 }
+
+#[cfg(test)]
+pub fn test_market() -> Rc<Market> {
+    Rc::new(Market {
+        symbol: "BTC/USDT".into(),
+        base_asset: Rc::new(Asset {
+            symbol: "BTC".into(),
+            decimals: 7,
+        }),
+        quote_asset: Rc::new(Asset {
+            symbol: "USDT".into(),
+            decimals: 2,
+        }),
+        tick: 1,
+        multiplier: 1,
+        quote_decimals: 2,
+        base_decimals: 5,
+        price_protection: None,
+        order_limits: Default::default(),
+    })
+}
+
+/// Order with given data, whose other fields tests can override
+#[cfg(test)]
+pub fn test_order(
+    market: &Rc<Market>,
+    participant_id: usize,
+    order_id: usize,
+    order_data: OrderType,
+) -> Order {
+    Order {
+        market: market.clone(),
+        participant_id,
+        order_id,
+        client_order_id: None,
+        expire_time: None,
+        self_trade_prevention: None,
+        cancel_on_disconnect: false,
+        reduce_only: false,
+        order_data,
+    }
+}
+
+#[cfg(test)]
+pub fn test_limit_order(
+    market: &Rc<Market>,
+    participant_id: usize,
+    order_id: usize,
+    side: Side,
+    price: u64,
+    quantity: u64,
+) -> Rc<Order> {
+    Rc::new(test_order(
+        market,
+        participant_id,
+        order_id,
+        OrderType::Limit(LimitOrder {
+            side,
+            price,
+            quantity,
+        }),
+    ))
+}
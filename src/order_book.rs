@@ -1,14 +1,30 @@
-use std::{cell::RefCell, cmp::min, collections::VecDeque, error::Error, rc::Rc};
+use std::{
+    cell::RefCell,
+    cmp::min,
+    collections::{BTreeMap, HashMap, VecDeque},
+    rc::Rc,
+};
 
 use intrusive_collections::{
-    intrusive_adapter, rbtree::CursorMut, Bound, KeyAdapter, RBTree, RBTreeLink,
+    intrusive_adapter, rbtree::CursorMut, Bound, KeyAdapter, LinkedList, LinkedListLink, RBTree,
+    RBTreeLink,
 };
+use itertools::Either;
 
-use crate::{execution_policy::ExecutionPolicy, market_data_policy::MarketDataPolicy, order::*};
+use crate::{
+    error::{BookError, EngineError, EngineErrorKind, NotFoundError},
+    execution_policy::ExecutionPolicy,
+    market_data_policy::MarketDataPolicy,
+    order::*,
+};
 
+#[derive(Clone)]
 pub struct OrderQuantity {
     pub order: Rc<Order>,
+    /// Quantity visible on the book (and available for matching)
     pub quantity: u64,
+    /// Reserve quantity of an iceberg order, not visible on the book
+    pub hidden_quantity: u64,
 }
 
 impl OrderQuantity {
@@ -16,6 +32,7 @@ impl OrderQuantity {
         Self {
             order: order.clone(),
             quantity: limit.quantity,
+            hidden_quantity: 0,
         }
     }
 
@@ -23,67 +40,399 @@ impl OrderQuantity {
         Self {
             order: order.clone(),
             quantity: market_order.quantity,
+            hidden_quantity: 0,
+        }
+    }
+
+    /// Tell total quantity including hidden quantity
+    pub fn get_total_quantity(&self) -> u64 {
+        self.quantity + self.hidden_quantity
+    }
+
+    /// Hide all but display quantity
+    pub fn hide_quantity(&mut self, display_quantity: u64) {
+        let total_quantity = self.get_total_quantity();
+        self.quantity = min(display_quantity, total_quantity);
+        self.hidden_quantity = total_quantity - self.quantity;
+    }
+
+    /// Part of quantity to take away from the order, taking from hidden quantity first
+    pub fn new_reduced_quantity(&self, quantity: u64) -> Self {
+        let hidden_quantity = min(self.hidden_quantity, quantity);
+        Self {
+            order: self.order.clone(),
+            quantity: min(quantity - hidden_quantity, self.quantity),
+            hidden_quantity,
+        }
+    }
+
+    /// Display next slice of hidden quantity, and tell if there was any
+    pub fn replenish_quantity(&mut self) -> bool {
+        match &self.order.order_data {
+            OrderType::Iceberg(iceberg) if 0 < self.hidden_quantity => {
+                self.hide_quantity(iceberg.display_quantity);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Executions of an aggressor order matched against the book
+#[derive(Default)]
+pub struct OrderFills {
+    pub quantity: u64,
+    /// Sum of executed quantity times price
+    pub value: u128,
+    pub last_price: Option<u64>,
+    /// Quantity of aggressor order decremented by self-trade prevention
+    pub self_trade_quantity: u64,
+    /// Rest of aggressor order is to be cancelled by self-trade prevention,
+    /// or because its execution was rejected
+    pub is_aggressor_cancelled: bool,
+    /// Some execution was rejected by execution policy
+    pub is_execution_rejected: bool,
+    /// Book order was kept after its execution was rejected, so rest of
+    /// aggressor order cannot rest on the book without crossing it
+    pub is_book_order_kept: bool,
+}
+
+/// Reason why order quantity was cancelled without resting on the book
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    /// Immediate order ran out of liquidity to match against
+    NoLiquidity,
+    /// Market order reached price protection band of the market
+    PriceProtection,
+    /// Order would match resting order of the same participant
+    SelfTradePrevention,
+    /// Execution policy rejected execution of order
+    ExecutionRejected,
+}
+
+/// How aggressor order is matched against book orders
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchRules {
+    pub self_trade_prevention: SelfTradePrevention,
+    pub rejected_execution: RejectedExecution,
+}
+
+/// What to do with book order, when execution policy rejects its execution
+///
+/// Either way matching continues with the next book order. When execution of
+/// aggressor order is rejected, its remainder is cancelled instead.
+/// Fill-or-Kill order is killed before matching, if execution policy would
+/// reject any of its executions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RejectedExecution {
+    /// Cancel book order, and release its promise
+    #[default]
+    CancelBookOrder,
+    /// Leave book order where it is, and cancel the rest of aggressor order
+    /// instead of letting it rest crossing the book order
+    KeepBookOrder,
+}
+
+/// Result of placing an order
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OrderOutcome {
+    /// Quantity executed immediately on arrival
+    pub filled_quantity: u64,
+    /// Volume weighted average price of immediate executions (rounded down)
+    pub average_price: Option<u64>,
+    /// Price of the last immediate execution
+    pub last_price: Option<u64>,
+    /// Quantity cancelled instead of resting on the book
+    pub cancelled_quantity: u64,
+    /// Value of quote market order (in quote currency), which was cancelled
+    /// as there was nothing left to match it against
+    pub cancelled_value: u64,
+    pub cancel_reason: Option<CancelReason>,
+    /// Errors of stop orders triggered by the order, which failed to be placed
+    pub rejected_triggered_orders: Vec<EngineError>,
+}
+
+impl From<OrderFills> for OrderOutcome {
+    fn from(fills: OrderFills) -> Self {
+        Self {
+            filled_quantity: fills.quantity,
+            average_price: fills
+                .value
+                .checked_div(fills.quantity as u128)
+                .and_then(|price| price.try_into().ok()),
+            last_price: fills.last_price,
+            cancelled_quantity: fills.self_trade_quantity,
+            cancelled_value: 0,
+            cancel_reason: (0 < fills.self_trade_quantity)
+                .then_some(CancelReason::SelfTradePrevention),
+            rejected_triggered_orders: Vec::new(),
+        }
+    }
+}
+
+impl OrderFills {
+    /// Tell if rest of aggressor order is to be cancelled instead of resting
+    /// on the book
+    fn is_remainder_cancelled(&self) -> bool {
+        self.is_aggressor_cancelled || self.is_book_order_kept
+    }
+
+    /// Tell why the rest of aggressor order is to be cancelled
+    fn get_cancel_reason(&self, cancel_reason: CancelReason) -> CancelReason {
+        if self.is_execution_rejected {
+            CancelReason::ExecutionRejected
+        } else if self.is_aggressor_cancelled {
+            CancelReason::SelfTradePrevention
+        } else {
+            cancel_reason
+        }
+    }
+}
+
+impl OrderOutcome {
+    /// Cancel quantity left over from matching through the cancel hooks
+    fn cancel_remainder(
+        &mut self,
+        mut order_quantity: OrderQuantity,
+        cancel_reason: CancelReason,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<(), EngineError> {
+        if order_quantity.quantity == 0 {
+            return Ok(());
+        }
+        // Book order is promised only once placed, so there is nothing to release
+        // for remainder of one, which was cancelled before resting on the book
+        if order_quantity.order.get_book_limit().is_none() {
+            execution_policy.cancel_order(&mut order_quantity)?;
+        }
+        market_data_policy.handle_order_cancelled(&order_quantity);
+        self.cancelled_quantity += order_quantity.quantity;
+        self.cancel_reason = Some(cancel_reason);
+        Ok(())
+    }
+
+    /// Cancel value of quote market order, which was left unmatched, together
+    /// with quantity left over from matching
+    fn cancel_quote_remainder(
+        &mut self,
+        order_quantity: OrderQuantity,
+        value: u64,
+        cancel_reason: CancelReason,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<(), EngineError> {
+        if order_quantity.quantity == 0 && 0 < value {
+            // Nothing of immediate order was promised, so only the hooks are told
+            market_data_policy.handle_order_cancelled(&order_quantity);
+            self.cancel_reason = Some(cancel_reason);
+        }
+        self.cancelled_value += value;
+        self.cancel_remainder(
+            order_quantity,
+            cancel_reason,
+            execution_policy,
+            market_data_policy,
+        )
+    }
+}
+
+/// Order resting on the book, queued at its price level
+pub struct BookOrder {
+    pub price: u64,
+    pub order_quantity: RefCell<OrderQuantity>,
+    link: LinkedListLink,
+}
+
+intrusive_adapter!(pub BookOrderAdapter = Rc<BookOrder>: BookOrder { link: LinkedListLink });
+
+impl BookOrder {
+    pub fn new(order_quantity: OrderQuantity, price: u64) -> Self {
+        Self {
+            price,
+            order_quantity: RefCell::new(order_quantity),
+            link: LinkedListLink::new(),
         }
     }
 }
 
 pub struct PriceLevel {
     pub price: u64,
-    orders: RefCell<VecDeque<OrderQuantity>>,
+    orders: RefCell<LinkedList<BookOrderAdapter>>,
     link: RBTreeLink,
 }
 
 intrusive_adapter!(pub PriceLevelAdapter = Rc<PriceLevel>: PriceLevel { link: RBTreeLink });
 
 impl PriceLevel {
-    pub fn new(book_order: OrderQuantity, limit: &LimitOrder) -> Self {
+    pub fn new(price: u64) -> Self {
         Self {
-            price: limit.price,
-            orders: RefCell::new(vec![book_order].into()),
+            price,
+            orders: RefCell::new(LinkedList::new(BookOrderAdapter::new())),
             link: RBTreeLink::new(),
         }
     }
 
-    pub fn place_order(
-        &self,
-        mut book_order: OrderQuantity,
-        execution_policy: &impl ExecutionPolicy,
-        market_data_policy: &impl MarketDataPolicy,
-    ) -> Result<(), Box<dyn Error>> {
-        execution_policy.place_order(&mut book_order)?;
-        market_data_policy.handle_order_placed(&book_order);
+    pub fn place_order(&self, book_order: Rc<BookOrder>) {
         self.orders.borrow_mut().push_back(book_order);
-        Ok(())
     }
 
     pub fn match_order(
         &self,
         aggressor_order: &mut OrderQuantity,
+        match_rules: MatchRules,
+        book_orders: &mut HashMap<(usize, usize), Rc<BookOrder>>,
+        fills: &mut OrderFills,
         execution_policy: &impl ExecutionPolicy,
         market_data_policy: &impl MarketDataPolicy,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), EngineError> {
         let mut orders = self.orders.borrow_mut();
-        while let Some(book_order) = orders.front_mut() {
-            if aggressor_order.quantity == 0 {
+        // Book orders kept after their execution was rejected, in queue order
+        let mut skipped_orders = Vec::new();
+        while let Some(book_order) = orders.front().get() {
+            if aggressor_order.quantity == 0 || fills.is_aggressor_cancelled {
                 break;
             }
-            let mut executed_quantity = min(aggressor_order.quantity, book_order.quantity);
-            execution_policy.execute_orders(&mut executed_quantity, aggressor_order, book_order)?;
+            let mut order_quantity = book_order.order_quantity.borrow_mut();
+
+            if order_quantity.order.participant_id == aggressor_order.order.participant_id {
+                let is_book_order_cancelled = match match_rules.self_trade_prevention {
+                    SelfTradePrevention::CancelNewest => {
+                        fills.is_aggressor_cancelled = true;
+                        false
+                    }
+                    SelfTradePrevention::CancelOldest => true,
+                    SelfTradePrevention::CancelBoth => {
+                        fills.is_aggressor_cancelled = true;
+                        true
+                    }
+                    SelfTradePrevention::DecrementAndCancel => {
+                        let quantity = min(
+                            aggressor_order.quantity,
+                            order_quantity.get_total_quantity(),
+                        );
+                        aggressor_order.quantity -= quantity;
+                        fills.self_trade_quantity += quantity;
+                        if quantity < order_quantity.get_total_quantity() {
+                            let mut reduced_order = order_quantity.new_reduced_quantity(quantity);
+                            execution_policy.cancel_order(&mut reduced_order)?;
+                            market_data_policy.handle_order_cancelled(&reduced_order);
+                            order_quantity.quantity -= reduced_order.quantity;
+                            order_quantity.hidden_quantity -= reduced_order.hidden_quantity;
+                            false
+                        } else {
+                            true
+                        }
+                    }
+                };
+                if is_book_order_cancelled {
+                    execution_policy.cancel_order(&mut order_quantity)?;
+                    market_data_policy.handle_order_cancelled(&order_quantity);
+                    book_orders.remove(&(
+                        order_quantity.order.participant_id,
+                        order_quantity.order.order_id,
+                    ));
+                    drop(order_quantity);
+                    orders.pop_front();
+                }
+                continue;
+            }
+
+            let mut executed_quantity = min(aggressor_order.quantity, order_quantity.quantity);
+            if let Err(err) = execution_policy.execute_orders(
+                &mut executed_quantity,
+                aggressor_order,
+                &mut order_quantity,
+            ) {
+                fills.is_execution_rejected = true;
+                if err.participant_id == aggressor_order.order.participant_id
+                    && err.order_id == aggressor_order.order.order_id
+                {
+                    fills.is_aggressor_cancelled = true;
+                    continue;
+                }
+                match match_rules.rejected_execution {
+                    RejectedExecution::CancelBookOrder => {
+                        // Book order leaves the book even if its promise cannot be released
+                        let _ = execution_policy.cancel_order(&mut order_quantity);
+                        market_data_policy.handle_order_cancelled(&order_quantity);
+                        book_orders.remove(&(
+                            order_quantity.order.participant_id,
+                            order_quantity.order.order_id,
+                        ));
+                        drop(order_quantity);
+                        orders.pop_front();
+                    }
+                    RejectedExecution::KeepBookOrder => {
+                        fills.is_book_order_kept = true;
+                        drop(order_quantity);
+                        skipped_orders.extend(orders.pop_front());
+                    }
+                }
+                continue;
+            }
             market_data_policy.handle_order_executed(
                 executed_quantity,
                 aggressor_order,
-                book_order,
+                &order_quantity,
             );
-            if book_order.quantity == 0 {
-                orders.pop_front();
+            fills.quantity += executed_quantity;
+            fills.value += executed_quantity as u128 * self.price as u128;
+            fills.last_price = Some(self.price);
+            if order_quantity.quantity == 0 {
+                if order_quantity.replenish_quantity() {
+                    // Next slice of iceberg order goes to the back of the queue
+                    market_data_policy.handle_order_placed(&order_quantity);
+                    drop(order_quantity);
+                    let book_order = orders.pop_front();
+                    orders.push_back(book_order.ok_or_else(|| {
+                        EngineError::new(&aggressor_order.order, BookError::Inconsistent)
+                    })?);
+                } else {
+                    book_orders.remove(&(
+                        order_quantity.order.participant_id,
+                        order_quantity.order.order_id,
+                    ));
+                    drop(order_quantity);
+                    orders.pop_front();
+                }
             }
         }
+        // Skipped book orders keep their priority
+        for book_order in skipped_orders.into_iter().rev() {
+            orders.push_front(book_order);
+        }
         Ok(())
     }
 
+    pub fn cancel_order(
+        &self,
+        book_order: &BookOrder,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<u64, EngineError> {
+        let mut order_quantity = book_order.order_quantity.borrow_mut();
+        execution_policy.cancel_order(&mut order_quantity)?;
+        market_data_policy.handle_order_cancelled(&order_quantity);
+        // SAFETY: Book orders are indexed by the price of the level they were
+        // placed at, and are only unlinked from its queue together with removing
+        // them from the index, so this book order is linked into this queue
+        unsafe { self.orders.borrow_mut().cursor_mut_from_ptr(book_order) }.remove();
+        Ok(order_quantity.get_total_quantity())
+    }
+
     pub fn is_empty(&self) -> bool {
         self.orders.borrow().is_empty()
     }
+
+    /// Tell total quantity of all orders at this level
+    pub fn get_quantity(&self) -> u64 {
+        self.orders
+            .borrow()
+            .iter()
+            .map(|book_order| book_order.order_quantity.borrow().get_total_quantity())
+            .sum()
+    }
 }
 
 impl<'a> KeyAdapter<'a> for PriceLevelAdapter {
@@ -96,6 +445,7 @@ impl<'a> KeyAdapter<'a> for PriceLevelAdapter {
 #[derive(Default)]
 pub struct PriceLevels {
     levels: RBTree<PriceLevelAdapter>,
+    orders: HashMap<(usize, usize), Rc<BookOrder>>,
 }
 
 trait PriceLevelMatchOps {
@@ -104,17 +454,20 @@ trait PriceLevelMatchOps {
         levels: &'a mut RBTree<PriceLevelAdapter>,
     ) -> CursorMut<'a, PriceLevelAdapter>;
     fn move_next<'a>(&self, cursor: &mut CursorMut<'a, PriceLevelAdapter>);
+    fn remove_level<'a>(&self, cursor: &mut CursorMut<'a, PriceLevelAdapter>);
     fn is_finished(&self, order_quantity: &OrderQuantity, level_price: u64) -> bool;
 }
 
 struct MarketMatchOps {
     book_side: Side,
+    protection_price: Option<u64>,
 }
 
 impl MarketMatchOps {
-    fn new(order_side: Side) -> Self {
+    fn new(order_side: Side, protection_price: Option<u64>) -> Self {
         Self {
             book_side: order_side.opposite(),
+            protection_price,
         }
     }
 }
@@ -137,8 +490,22 @@ impl PriceLevelMatchOps for MarketMatchOps {
         }
     }
 
-    fn is_finished(&self, order_quantity: &OrderQuantity, _level_price: u64) -> bool {
+    fn remove_level<'a>(&self, cursor: &mut CursorMut<'a, PriceLevelAdapter>) {
+        // Removal moves cursor to the next level in ascending order
+        cursor.remove();
+        if let Side::Bid = self.book_side {
+            cursor.move_prev();
+        }
+    }
+
+    fn is_finished(&self, order_quantity: &OrderQuantity, level_price: u64) -> bool {
         order_quantity.quantity == 0
+            || self
+                .protection_price
+                .is_some_and(|protection_price| match self.book_side {
+                    Side::Bid => level_price < protection_price,
+                    Side::Ask => protection_price < level_price,
+                })
     }
 }
 
@@ -174,6 +541,14 @@ impl PriceLevelMatchOps for LimitMatchOps {
         }
     }
 
+    fn remove_level<'a>(&self, cursor: &mut CursorMut<'a, PriceLevelAdapter>) {
+        // Removal moves cursor to the next level in ascending order
+        cursor.remove();
+        if let Side::Bid = self.book_side {
+            cursor.move_prev();
+        }
+    }
+
     fn is_finished(&self, order_quantity: &OrderQuantity, level_price: u64) -> bool {
         order_quantity.quantity == 0
             || match self.book_side {
@@ -187,38 +562,52 @@ impl PriceLevels {
     fn match_order_side(
         &mut self,
         order_quantity: &mut OrderQuantity,
+        match_rules: MatchRules,
         execution_policy: &impl ExecutionPolicy,
         market_data_policy: &impl MarketDataPolicy,
         ops: &impl PriceLevelMatchOps,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<OrderFills, EngineError> {
         let mut cursor = ops.begin_ops(&mut self.levels);
+        let mut fills = OrderFills::default();
 
         while let Some(level) = cursor.get() {
-            if ops.is_finished(order_quantity, level.price) {
+            if ops.is_finished(order_quantity, level.price) || fills.is_aggressor_cancelled {
                 break;
             }
 
-            level.match_order(order_quantity, execution_policy, market_data_policy)?;
+            level.match_order(
+                order_quantity,
+                match_rules,
+                &mut self.orders,
+                &mut fills,
+                execution_policy,
+                market_data_policy,
+            )?;
             if level.is_empty() {
-                cursor.remove();
+                ops.remove_level(&mut cursor);
+            } else {
+                ops.move_next(&mut cursor);
             }
-            ops.move_next(&mut cursor);
         }
-        Ok(())
+        Ok(fills)
     }
 
+    /// Match market order, but not at prices beyond protection price if any
     pub fn match_market_order(
         &mut self,
         order_quantity: &mut OrderQuantity,
         market_order: &MarketOrder,
+        protection_price: Option<u64>,
+        match_rules: MatchRules,
         execution_policy: &impl ExecutionPolicy,
         market_data_policy: &impl MarketDataPolicy,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<OrderFills, EngineError> {
         self.match_order_side(
             order_quantity,
+            match_rules,
             execution_policy,
             market_data_policy,
-            &MarketMatchOps::new(market_order.side),
+            &MarketMatchOps::new(market_order.side, protection_price),
         )
     }
 
@@ -226,55 +615,330 @@ impl PriceLevels {
         &mut self,
         order_quantity: &mut OrderQuantity,
         limit: &LimitOrder,
+        match_rules: MatchRules,
         execution_policy: &impl ExecutionPolicy,
         market_data_policy: &impl MarketDataPolicy,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<OrderFills, EngineError> {
         self.match_order_side(
             order_quantity,
+            match_rules,
             execution_policy,
             market_data_policy,
             &LimitMatchOps::new(limit.side, limit.price),
         )
     }
 
+    /// Tell the best price of the levels on given side of the book
+    pub fn get_best_price(&self, book_side: Side) -> Option<u64> {
+        match book_side {
+            Side::Bid => self.levels.back().get().map(|level| level.price),
+            Side::Ask => self.levels.front().get().map(|level| level.price),
+        }
+    }
+
+    /// Tell executions, which would fully match limit order of participant,
+    /// without matching it, or None if it cannot be fully matched
+    ///
+    /// Order cannot be filled, if it would meet an order of the same participant
+    /// first, as self-trade prevention would cancel either of them.
+    pub fn get_fill_executions(
+        &self,
+        participant_id: usize,
+        limit: &LimitOrder,
+    ) -> Option<Vec<(u64, OrderQuantity)>> {
+        let levels = match limit.side {
+            Side::Bid => Either::Left(self.levels.iter()),
+            Side::Ask => Either::Right(self.levels.iter().rev()),
+        };
+        let mut executions = Vec::new();
+        let mut quantity = limit.quantity;
+        let mut execute = |order_quantity: &OrderQuantity, available_quantity| {
+            let executed_quantity = min(quantity, available_quantity);
+            if 0 < executed_quantity {
+                quantity -= executed_quantity;
+                executions.push((
+                    executed_quantity,
+                    OrderQuantity {
+                        order: order_quantity.order.clone(),
+                        quantity: order_quantity.quantity,
+                        hidden_quantity: order_quantity.hidden_quantity,
+                    },
+                ));
+            }
+            quantity == 0
+        };
+        for level in levels.take_while(|level| match limit.side {
+            Side::Bid => level.price <= limit.price,
+            Side::Ask => limit.price <= level.price,
+        }) {
+            let orders = level.orders.borrow();
+            for book_order in orders.iter() {
+                let order_quantity = book_order.order_quantity.borrow();
+                if order_quantity.order.participant_id == participant_id {
+                    return None;
+                }
+                if execute(&order_quantity, order_quantity.quantity) {
+                    return Some(executions);
+                }
+            }
+            // Hidden quantity is displayed only after all visible quantity of level
+            for book_order in orders.iter() {
+                let order_quantity = book_order.order_quantity.borrow();
+                if execute(&order_quantity, order_quantity.hidden_quantity) {
+                    return Some(executions);
+                }
+            }
+        }
+        None
+    }
+
+    /// Tell base quantity, which can be matched for given value in quote
+    /// currency, and the value left unspent
+    ///
+    /// Quantity taken from the last level is rounded down to market's lot size,
+    /// so that its value does not exceed the rest of given value, and the rest
+    /// of that value is left unspent too.
+    /// Levels beyond protection price (if any) are not taken.
+    pub fn get_quantity_for_value(
+        &self,
+        side: Side,
+        value: u64,
+        protection_price: Option<u64>,
+        market: &Market,
+    ) -> Option<(u64, u64)> {
+        let levels = match side {
+            Side::Bid => Either::Left(self.levels.iter()),
+            Side::Ask => Either::Right(self.levels.iter().rev()),
+        };
+        let mut quantity: u64 = 0;
+        let mut remaining_value = value;
+        for level in levels.take_while(|level| {
+            protection_price.is_none_or(|protection_price| match side {
+                Side::Bid => level.price <= protection_price,
+                Side::Ask => protection_price <= level.price,
+            })
+        }) {
+            let level_quantity = level.get_quantity();
+            let level_value = calculate_value(
+                level_quantity,
+                level.price,
+                market.base_decimals,
+                market.quote_decimals,
+            )?;
+            if level_value <= remaining_value {
+                quantity = quantity.checked_add(level_quantity)?;
+                remaining_value -= level_value;
+            } else {
+                // Largest quantity, for which exact value (before rounding in
+                // calculate_value) is still within remaining value
+                let k_base = 10u128.checked_pow(market.base_decimals as u32)?;
+                let partial_quantity =
+                    (remaining_value as u128 * k_base).checked_div(level.price as u128)?;
+                let partial_quantity: u64 = (partial_quantity
+                    - partial_quantity % market.multiplier as u128)
+                    .try_into()
+                    .ok()?;
+                let partial_value = calculate_value(
+                    partial_quantity,
+                    level.price,
+                    market.base_decimals,
+                    market.quote_decimals,
+                )?;
+                quantity = quantity.checked_add(partial_quantity)?;
+                return Some((quantity, remaining_value.saturating_sub(partial_value)));
+            }
+        }
+        Some((quantity, remaining_value))
+    }
+
     pub fn place_limit_order(
         &mut self,
         mut order_quantity: OrderQuantity,
         limit: &LimitOrder,
         execution_policy: &impl ExecutionPolicy,
         market_data_policy: &impl MarketDataPolicy,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), EngineError> {
+        if order_quantity.quantity == 0 {
+            // Order was fully matched, there is nothing left to place
+            return Ok(());
+        }
+
+        execution_policy.place_order(&mut order_quantity)?;
+        market_data_policy.handle_order_placed(&order_quantity);
+
+        let key = (
+            order_quantity.order.participant_id,
+            order_quantity.order.order_id,
+        );
+        let book_order = Rc::new(BookOrder::new(order_quantity, limit.price));
+        self.orders.insert(key, book_order.clone());
+
         let mut cursor = self.levels.lower_bound_mut(Bound::Included(&limit.price));
 
-        if let Some(level) = cursor.get() {
-            if limit.price == level.price {
+        match cursor.get() {
+            Some(level) if limit.price == level.price => {
                 // Level already exists: Add order to that level
-                level.place_order(order_quantity, execution_policy, market_data_policy)
-            } else {
-                // There exists level before, which we should insert new level
-                execution_policy.place_order(&mut order_quantity)?;
-                market_data_policy.handle_order_placed(&order_quantity);
-                cursor.insert_before(Rc::new(PriceLevel::new(order_quantity, limit)));
-                Ok(())
+                level.place_order(book_order);
             }
-        } else {
-            // We should insert at the end
-            execution_policy.place_order(&mut order_quantity)?;
-            market_data_policy.handle_order_placed(&order_quantity);
-            cursor.insert_before(Rc::new(PriceLevel::new(order_quantity, limit)));
-            Ok(())
+            _ => {
+                // Insert new level before the next one (or at the end)
+                let level = PriceLevel::new(limit.price);
+                level.place_order(book_order);
+                cursor.insert_before(Rc::new(level));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn cancel_order(
+        &mut self,
+        participant_id: usize,
+        order_id: usize,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<u64, EngineError> {
+        let book_order = self
+            .orders
+            .get(&(participant_id, order_id))
+            .ok_or_else(|| EngineError::with_ids(participant_id, order_id, NotFoundError::Order))?
+            .clone();
+        let mut cursor = self.levels.find_mut(&book_order.price);
+        let level = cursor.get().ok_or_else(|| {
+            EngineError::with_ids(participant_id, order_id, BookError::Inconsistent)
+        })?;
+        let quantity = level.cancel_order(&book_order, execution_policy, market_data_policy)?;
+        self.orders.remove(&(participant_id, order_id));
+        if level.is_empty() {
+            cursor.remove();
+        }
+        Ok(quantity)
+    }
+
+    /// Reduce quantity of resting order keeping its place in the queue, and tell
+    /// if that was possible (quantity cannot be increased in place)
+    pub fn reduce_order(
+        &mut self,
+        participant_id: usize,
+        order_id: usize,
+        quantity: u64,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<bool, EngineError> {
+        let book_order = self
+            .orders
+            .get(&(participant_id, order_id))
+            .ok_or_else(|| EngineError::with_ids(participant_id, order_id, NotFoundError::Order))?;
+        let mut order_quantity = book_order.order_quantity.borrow_mut();
+        let total_quantity = order_quantity.get_total_quantity();
+        if total_quantity < quantity {
+            return Ok(false);
+        }
+        if quantity < total_quantity {
+            // Release only the difference
+            let mut reduced_order = order_quantity.new_reduced_quantity(total_quantity - quantity);
+            execution_policy.cancel_order(&mut reduced_order)?;
+            market_data_policy.handle_order_cancelled(&reduced_order);
+            order_quantity.quantity -= reduced_order.quantity;
+            order_quantity.hidden_quantity -= reduced_order.hidden_quantity;
+        }
+        Ok(true)
+    }
+
+    /// Tell the quantity of resting order
+    pub fn get_order_quantity(&self, participant_id: usize, order_id: usize) -> Option<u64> {
+        self.orders
+            .get(&(participant_id, order_id))
+            .map(|book_order| book_order.order_quantity.borrow().get_total_quantity())
+    }
+
+    pub fn get_order(&self, participant_id: usize, order_id: usize) -> Option<Rc<Order>> {
+        self.orders
+            .get(&(participant_id, order_id))
+            .map(|book_order| book_order.order_quantity.borrow().order.clone())
+    }
+
+    pub fn contains_order(&self, participant_id: usize, order_id: usize) -> bool {
+        self.orders.contains_key(&(participant_id, order_id))
+    }
+}
+
+/// Stop orders waiting for last trade price to reach their trigger price
+#[derive(Default)]
+pub struct StopOrders {
+    /// Buy stops, triggered when price rises to or above trigger price
+    bid: BTreeMap<u64, VecDeque<Rc<Order>>>,
+    /// Sell stops, triggered when price falls to or below trigger price
+    ask: BTreeMap<u64, VecDeque<Rc<Order>>>,
+    orders: HashMap<(usize, usize), (Side, u64)>,
+}
+
+impl StopOrders {
+    pub fn place_order(&mut self, order: Rc<Order>, side: Side, trigger_price: u64) {
+        self.orders.insert(
+            (order.participant_id, order.order_id),
+            (side, trigger_price),
+        );
+        let triggers = match side {
+            Side::Bid => &mut self.bid,
+            Side::Ask => &mut self.ask,
+        };
+        triggers.entry(trigger_price).or_default().push_back(order);
+    }
+
+    pub fn cancel_order(&mut self, participant_id: usize, order_id: usize) -> Option<Rc<Order>> {
+        let (side, trigger_price) = self.orders.remove(&(participant_id, order_id))?;
+        let triggers = match side {
+            Side::Bid => &mut self.bid,
+            Side::Ask => &mut self.ask,
+        };
+        let orders = triggers.get_mut(&trigger_price)?;
+        let position = orders
+            .iter()
+            .position(|x| x.participant_id == participant_id && x.order_id == order_id)?;
+        let order = orders.remove(position);
+        if orders.is_empty() {
+            triggers.remove(&trigger_price);
+        }
+        order
+    }
+
+    /// Take next stop order triggered at given price
+    ///
+    /// Buy stops are taken before sell stops, nearest trigger price first, and
+    /// in order of arrival at the same trigger price.
+    pub fn pop_triggered(&mut self, last_price: u64) -> Option<Rc<Order>> {
+        let mut entry = self
+            .bid
+            .first_entry()
+            .filter(|entry| *entry.key() <= last_price)
+            .or_else(|| {
+                self.ask
+                    .last_entry()
+                    .filter(|entry| last_price <= *entry.key())
+            })?;
+        let order = entry.get_mut().pop_front()?;
+        if entry.get().is_empty() {
+            entry.remove();
         }
+        self.orders.remove(&(order.participant_id, order.order_id));
+        Some(order)
     }
 
-    // pub fn place_stop(&mut self, order: Rc<Order>, stop: &StopOrder) {
-    //     Place trigger at given level, that will place limit if triggered
-    // }
+    pub fn contains_order(&self, participant_id: usize, order_id: usize) -> bool {
+        self.orders.contains_key(&(participant_id, order_id))
+    }
 }
 
 pub struct OrderBook {
     pub market: Rc<Market>,
     bid: PriceLevels,
     ask: PriceLevels,
+    stop_orders: StopOrders,
+    triggered_orders: Vec<Rc<Order>>,
+    last_price: Option<u64>,
+    /// Self-trade prevention mode of participants, for orders without one
+    self_trade_prevention: HashMap<usize, SelfTradePrevention>,
+    rejected_execution: RejectedExecution,
 }
 
 impl OrderBook {
@@ -283,93 +947,1484 @@ impl OrderBook {
             market,
             bid: Default::default(),
             ask: Default::default(),
+            stop_orders: Default::default(),
+            triggered_orders: Vec::new(),
+            last_price: None,
+            self_trade_prevention: HashMap::new(),
+            rejected_execution: Default::default(),
+        }
+    }
+
+    /// Set what to do with book orders, which execution policy refuses to execute
+    pub fn set_rejected_execution(&mut self, rejected_execution: RejectedExecution) {
+        self.rejected_execution = rejected_execution;
+    }
+
+    /// Set self-trade prevention mode for orders of participant without one
+    pub fn set_self_trade_prevention(
+        &mut self,
+        participant_id: usize,
+        self_trade_prevention: SelfTradePrevention,
+    ) {
+        self.self_trade_prevention
+            .insert(participant_id, self_trade_prevention);
+    }
+
+    /// Tell self-trade prevention mode of order, or its participant, or the default one
+    fn get_self_trade_prevention(&self, order: &Order) -> SelfTradePrevention {
+        order
+            .self_trade_prevention
+            .or_else(|| {
+                self.self_trade_prevention
+                    .get(&order.participant_id)
+                    .copied()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Price of the most recent trade
+    pub fn get_last_price(&self) -> Option<u64> {
+        self.last_price
+    }
+
+    /// Tell the best price on given side of the book
+    pub fn get_best_price(&self, side: Side) -> Option<u64> {
+        match side {
+            Side::Bid => self.bid.get_best_price(side),
+            Side::Ask => self.ask.get_best_price(side),
         }
     }
 
+    /// Stop orders triggered by the most recently placed order
+    pub fn get_triggered_orders(&self) -> &[Rc<Order>] {
+        &self.triggered_orders
+    }
+
     pub fn place_order(
         &mut self,
         order: Rc<Order>,
         execution_policy: &impl ExecutionPolicy,
         market_data_policy: &impl MarketDataPolicy,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<OrderOutcome, EngineError> {
+        // Index would lose track of the order already resting under the same ids
+        if self.contains_order(order.participant_id, order.order_id) {
+            return Err(EngineError::new(&order, BookError::DuplicateOrder));
+        }
+        self.triggered_orders.clear();
+        let mut outcome = self.match_order(order, execution_policy, market_data_policy)?;
+        if let Some(last_price) = outcome.last_price {
+            self.last_price = Some(last_price);
+        }
+        outcome.rejected_triggered_orders =
+            self.trigger_stop_orders(execution_policy, market_data_policy);
+        Ok(outcome)
+    }
+
+    /// Place stop orders triggered by last price one by one, until there is
+    /// no more triggered, so that cascades of stop orders are handled too, and
+    /// tell errors of triggered orders, which failed
+    fn trigger_stop_orders(
+        &mut self,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Vec<EngineError> {
+        let mut errors = Vec::new();
+        while let Some(stop_order) = self
+            .last_price
+            .and_then(|last_price| self.stop_orders.pop_triggered(last_price))
+        {
+            self.triggered_orders.push(stop_order.clone());
+            let Some(order) = stop_order.new_triggered_order() else {
+                errors.push(EngineError::new(&stop_order, BookError::InvalidOrderType));
+                continue;
+            };
+            // Failure of triggered order must not fail the order that triggered it
+            match self.match_order(Rc::new(order), execution_policy, market_data_policy) {
+                Ok(OrderOutcome {
+                    last_price: Some(last_price),
+                    ..
+                }) => self.last_price = Some(last_price),
+                Ok(_) => {}
+                Err(err) => errors.push(err),
+            }
+        }
+        errors
+    }
+
+    /// Tell the worst price market order may match at, given the best price at arrival
+    fn get_protection_price(&self, order_side: Side) -> Option<u64> {
+        let price_protection = self.market.price_protection?;
+        let best_price = self.get_best_price(order_side.opposite())?;
+        price_protection.get_protection_price(order_side, best_price, self.market.tick)
+    }
+
+    /// Tell the price post-only order would rest at, which is one tick away
+    /// from the opposite side if it would cross, and re-pricing is allowed
+    fn get_post_only_price(
+        &self,
+        order: &Order,
+        post_only: &PostOnlyOrder,
+    ) -> Result<u64, EngineError> {
+        let limit = &post_only.limit;
+        let best_price = self.get_best_price(limit.side.opposite());
+        let crossed_price = match limit.side {
+            Side::Bid => best_price.filter(|best_price| *best_price <= limit.price),
+            Side::Ask => best_price.filter(|best_price| limit.price <= *best_price),
+        };
+        match crossed_price {
+            None => Ok(limit.price),
+            Some(_) if !post_only.reprice => {
+                Err(EngineError::new(order, BookError::PostOnlyWouldCross))
+            }
+            Some(best_price) => match limit.side {
+                Side::Bid => best_price
+                    .checked_sub(self.market.tick)
+                    .filter(|price| 0 < *price),
+                Side::Ask => best_price.checked_add(self.market.tick),
+            }
+            .ok_or_else(|| EngineError::new(order, BookError::PostOnlyCannotReprice)),
+        }
+    }
+
+    /// Match order against the book and tell the outcome
+    fn match_order(
+        &mut self,
+        order: Rc<Order>,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<OrderOutcome, EngineError> {
+        let match_rules = MatchRules {
+            self_trade_prevention: self.get_self_trade_prevention(&order),
+            rejected_execution: self.rejected_execution,
+        };
         match &order.order_data {
             OrderType::Limit(limit) => {
                 let mut order_quantity = OrderQuantity::new_limit_order(order.clone(), limit);
-                match limit.side {
-                    Side::Bid => {
-                        self.ask.match_limit_order(
-                            &mut order_quantity,
-                            &limit,
-                            execution_policy,
-                            market_data_policy,
-                        )?;
-                        self.bid.place_limit_order(
-                            order_quantity,
-                            &limit,
-                            execution_policy,
-                            market_data_policy,
-                        )
-                    }
-                    Side::Ask => {
-                        self.bid.match_limit_order(
-                            &mut order_quantity,
-                            &limit,
-                            execution_policy,
-                            market_data_policy,
-                        )?;
-                        self.ask.place_limit_order(
-                            order_quantity,
-                            &limit,
-                            execution_policy,
-                            market_data_policy,
-                        )
-                    }
+                let (levels, opposite_levels) = match limit.side {
+                    Side::Bid => (&mut self.bid, &mut self.ask),
+                    Side::Ask => (&mut self.ask, &mut self.bid),
+                };
+                let fills = opposite_levels.match_limit_order(
+                    &mut order_quantity,
+                    limit,
+                    match_rules,
+                    execution_policy,
+                    market_data_policy,
+                )?;
+                let is_remainder_cancelled = fills.is_remainder_cancelled();
+                let cancel_reason = fills.get_cancel_reason(CancelReason::SelfTradePrevention);
+                let mut outcome: OrderOutcome = fills.into();
+                if is_remainder_cancelled {
+                    outcome.cancel_remainder(
+                        order_quantity,
+                        cancel_reason,
+                        execution_policy,
+                        market_data_policy,
+                    )?;
+                } else {
+                    levels.place_limit_order(
+                        order_quantity,
+                        limit,
+                        execution_policy,
+                        market_data_policy,
+                    )?;
                 }
+                Ok(outcome)
             }
-            OrderType::ImmediateOrCancel(limit) => {
+            OrderType::PostOnly(post_only) => {
+                let limit = &post_only.limit;
+                let price = self.get_post_only_price(&order, post_only)?;
+                let order = if price == limit.price {
+                    order.clone()
+                } else {
+                    Rc::new(
+                        order
+                            .new_amended_order(price, limit.quantity)
+                            .ok_or_else(|| EngineError::new(&order, BookError::InvalidOrderType))?,
+                    )
+                };
+                let levels = match limit.side {
+                    Side::Bid => &mut self.bid,
+                    Side::Ask => &mut self.ask,
+                };
+                let limit = order
+                    .get_book_limit()
+                    .ok_or_else(|| EngineError::new(&order, BookError::InvalidOrderType))?;
+                levels.place_limit_order(
+                    OrderQuantity::new_limit_order(order.clone(), limit),
+                    limit,
+                    execution_policy,
+                    market_data_policy,
+                )?;
+                Ok(OrderOutcome::default())
+            }
+            OrderType::Iceberg(iceberg) => {
+                let limit = &iceberg.limit;
                 let mut order_quantity = OrderQuantity::new_limit_order(order.clone(), limit);
-                match limit.side {
-                    Side::Bid => {
-                        self.ask.match_limit_order(
-                            &mut order_quantity,
-                            &limit,
-                            execution_policy,
-                            market_data_policy,
-                        )
-                    },
-                    Side::Ask => {
-                        self.bid.match_limit_order(
-                            &mut order_quantity,
-                            &limit,
-                            execution_policy,
-                            market_data_policy,
-                        )
-                    }
+                let (levels, opposite_levels) = match limit.side {
+                    Side::Bid => (&mut self.bid, &mut self.ask),
+                    Side::Ask => (&mut self.ask, &mut self.bid),
+                };
+                // Whole quantity is matched as an aggressor, and only the rest is hidden
+                let fills = opposite_levels.match_limit_order(
+                    &mut order_quantity,
+                    limit,
+                    match_rules,
+                    execution_policy,
+                    market_data_policy,
+                )?;
+                let is_remainder_cancelled = fills.is_remainder_cancelled();
+                let cancel_reason = fills.get_cancel_reason(CancelReason::SelfTradePrevention);
+                let mut outcome: OrderOutcome = fills.into();
+                if is_remainder_cancelled {
+                    outcome.cancel_remainder(
+                        order_quantity,
+                        cancel_reason,
+                        execution_policy,
+                        market_data_policy,
+                    )?;
+                } else {
+                    order_quantity.hide_quantity(iceberg.display_quantity);
+                    levels.place_limit_order(
+                        order_quantity,
+                        limit,
+                        execution_policy,
+                        market_data_policy,
+                    )?;
                 }
+                Ok(outcome)
+            }
+            OrderType::ImmediateOrCancel(limit) => {
+                let mut order_quantity = OrderQuantity::new_limit_order(order.clone(), limit);
+                let levels = match limit.side {
+                    Side::Bid => &mut self.ask,
+                    Side::Ask => &mut self.bid,
+                };
+                let fills = levels.match_limit_order(
+                    &mut order_quantity,
+                    limit,
+                    match_rules,
+                    execution_policy,
+                    market_data_policy,
+                )?;
+                let cancel_reason = fills.get_cancel_reason(CancelReason::NoLiquidity);
+                let mut outcome: OrderOutcome = fills.into();
+                outcome.cancel_remainder(
+                    order_quantity,
+                    cancel_reason,
+                    execution_policy,
+                    market_data_policy,
+                )?;
+                Ok(outcome)
+            }
+            OrderType::FillOrKill(limit) => {
+                let levels = match limit.side {
+                    Side::Bid => &mut self.ask,
+                    Side::Ask => &mut self.bid,
+                };
+                let executions = levels
+                    .get_fill_executions(order.participant_id, limit)
+                    .ok_or_else(|| EngineError::new(&order, BookError::FillOrKillNotFilled))?;
+                let mut order_quantity = OrderQuantity::new_limit_order(order.clone(), limit);
+                // Order is killed before its first execution, rather than left
+                // partially filled by execution rejected later on
+                execution_policy
+                    .check_executions(&order_quantity, &executions)
+                    .map_err(|err| {
+                        if err.participant_id == order.participant_id
+                            && err.order_id == order.order_id
+                        {
+                            err
+                        } else {
+                            EngineError::new(&order, BookError::FillOrKillNotFilled)
+                        }
+                    })?;
+                let fills = levels.match_limit_order(
+                    &mut order_quantity,
+                    limit,
+                    match_rules,
+                    execution_policy,
+                    market_data_policy,
+                )?;
+                let cancel_reason = fills.get_cancel_reason(CancelReason::ExecutionRejected);
+                let mut outcome: OrderOutcome = fills.into();
+                outcome.cancel_remainder(
+                    order_quantity,
+                    cancel_reason,
+                    execution_policy,
+                    market_data_policy,
+                )?;
+                Ok(outcome)
             }
             OrderType::Market(market_order) => {
                 let mut order_quantity =
                     OrderQuantity::new_market_order(order.clone(), market_order);
-                match market_order.side {
-                    Side::Bid => {
-                        self.ask.match_market_order(
-                            &mut order_quantity,
-                            &market_order,
-                            execution_policy,
-                            market_data_policy,
-                        )
-                    }
-                    Side::Ask => {
-                        self.bid.match_market_order(
-                            &mut order_quantity,
-                            &market_order,
-                            execution_policy,
-                            market_data_policy,
+                let protection_price = self.get_protection_price(market_order.side);
+                let levels = match market_order.side {
+                    Side::Bid => &mut self.ask,
+                    Side::Ask => &mut self.bid,
+                };
+                let fills = levels.match_market_order(
+                    &mut order_quantity,
+                    market_order,
+                    protection_price,
+                    match_rules,
+                    execution_policy,
+                    market_data_policy,
+                )?;
+                let cancel_reason = fills.get_cancel_reason(
+                    match levels.get_best_price(market_order.side.opposite()) {
+                        Some(_) => CancelReason::PriceProtection,
+                        None => CancelReason::NoLiquidity,
+                    },
+                );
+                let mut outcome: OrderOutcome = fills.into();
+                outcome.cancel_remainder(
+                    order_quantity,
+                    cancel_reason,
+                    execution_policy,
+                    market_data_policy,
+                )?;
+                Ok(outcome)
+            }
+            OrderType::QuoteMarket(quote_market) => {
+                let protection_price = self.get_protection_price(quote_market.side);
+                let opposite_levels = match quote_market.side {
+                    Side::Bid => &mut self.ask,
+                    Side::Ask => &mut self.bid,
+                };
+                let (quantity, value_left) = opposite_levels
+                    .get_quantity_for_value(
+                        quote_market.side,
+                        quote_market.value,
+                        protection_price,
+                        &self.market,
+                    )
+                    .ok_or_else(|| EngineError::new(&order, EngineErrorKind::Overflow))?;
+                let market_order = MarketOrder {
+                    side: quote_market.side,
+                    quantity,
+                };
+                let mut order_quantity =
+                    OrderQuantity::new_market_order(order.clone(), &market_order);
+                let fills = opposite_levels.match_market_order(
+                    &mut order_quantity,
+                    &market_order,
+                    protection_price,
+                    match_rules,
+                    execution_policy,
+                    market_data_policy,
+                )?;
+                // Value smaller than a lot of the next level is left, unless all
+                // levels within protection band were taken
+                let is_beyond_band = |price| {
+                    protection_price.is_some_and(|protection_price| match quote_market.side {
+                        Side::Bid => protection_price < price,
+                        Side::Ask => price < protection_price,
+                    })
+                };
+                let cancel_reason = fills.get_cancel_reason(
+                    match opposite_levels.get_best_price(quote_market.side.opposite()) {
+                        Some(price) if is_beyond_band(price) => CancelReason::PriceProtection,
+                        _ => CancelReason::NoLiquidity,
+                    },
+                );
+                let mut outcome: OrderOutcome = fills.into();
+                outcome.cancel_quote_remainder(
+                    order_quantity,
+                    value_left,
+                    cancel_reason,
+                    execution_policy,
+                    market_data_policy,
+                )?;
+                Ok(outcome)
+            }
+            OrderType::Stop(stop) => {
+                self.stop_orders
+                    .place_order(order.clone(), stop.side, stop.trigger_price);
+                Ok(OrderOutcome::default())
+            }
+            OrderType::StopLimit(stop) => {
+                self.stop_orders
+                    .place_order(order.clone(), stop.side, stop.trigger_price);
+                Ok(OrderOutcome::default())
+            }
+            _ => Err(EngineError::new(&order, BookError::InvalidOrderType)),
+        }
+    }
+
+    fn find_levels(
+        &mut self,
+        participant_id: usize,
+        order_id: usize,
+    ) -> Result<&mut PriceLevels, EngineError> {
+        if self.bid.contains_order(participant_id, order_id) {
+            Ok(&mut self.bid)
+        } else if self.ask.contains_order(participant_id, order_id) {
+            Ok(&mut self.ask)
+        } else {
+            Err(EngineError::with_ids(
+                participant_id,
+                order_id,
+                NotFoundError::Order,
+            ))
+        }
+    }
+
+    pub fn cancel_order(
+        &mut self,
+        participant_id: usize,
+        order_id: usize,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<u64, EngineError> {
+        // Stop orders are neither promised nor visible until triggered
+        if let Some(order) = self.stop_orders.cancel_order(participant_id, order_id) {
+            return match &order.order_data {
+                OrderType::Stop(stop) => Ok(stop.quantity),
+                OrderType::StopLimit(stop) => Ok(stop.quantity),
+                _ => Err(EngineError::new(&order, BookError::InvalidOrderType)),
+            };
+        }
+        self.find_levels(participant_id, order_id)?.cancel_order(
+            participant_id,
+            order_id,
+            execution_policy,
+            market_data_policy,
+        )
+    }
+
+    /// Reduce quantity of resting order in place, and tell if that was possible
+    pub fn reduce_order(
+        &mut self,
+        participant_id: usize,
+        order_id: usize,
+        quantity: u64,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<bool, EngineError> {
+        self.find_levels(participant_id, order_id)?.reduce_order(
+            participant_id,
+            order_id,
+            quantity,
+            execution_policy,
+            market_data_policy,
+        )
+    }
+
+    /// Check that resting order could be replaced by given order with the same
+    /// ids, as amending it would, without changing anything
+    pub fn check_replace_order(
+        &self,
+        order: &Rc<Order>,
+        execution_policy: &impl ExecutionPolicy,
+    ) -> Result<(), EngineError> {
+        let key = (order.participant_id, order.order_id);
+        let book_order = self
+            .bid
+            .orders
+            .get(&key)
+            .or_else(|| self.ask.orders.get(&key))
+            .ok_or_else(|| EngineError::new(order, NotFoundError::Order))?;
+        if let OrderType::PostOnly(post_only) = &order.order_data {
+            self.get_post_only_price(order, post_only)?;
+        }
+        let quantity = order
+            .get_quantity()
+            .ok_or_else(|| EngineError::new(order, BookError::InvalidOrderType))?;
+        execution_policy.check_replace_order(
+            &book_order.order_quantity.borrow(),
+            &OrderQuantity {
+                order: order.clone(),
+                quantity,
+                hidden_quantity: 0,
+            },
+        )
+    }
+
+    /// Tell the quantity of resting order
+    pub fn get_order_quantity(&self, participant_id: usize, order_id: usize) -> Option<u64> {
+        self.bid
+            .get_order_quantity(participant_id, order_id)
+            .or_else(|| self.ask.get_order_quantity(participant_id, order_id))
+    }
+
+    /// Tell if order is resting on the book, or waiting for its trigger
+    pub fn contains_order(&self, participant_id: usize, order_id: usize) -> bool {
+        self.stop_orders.contains_order(participant_id, order_id)
+            || self.bid.contains_order(participant_id, order_id)
+            || self.ask.contains_order(participant_id, order_id)
+    }
+
+    /// Get resting order, as it was placed on the book
+    pub fn get_order(&self, participant_id: usize, order_id: usize) -> Option<Rc<Order>> {
+        self.bid
+            .get_order(participant_id, order_id)
+            .or_else(|| self.ask.get_order(participant_id, order_id))
+    }
+}
+
+#[test]
+fn test_stop_orders_cascade() {
+    use crate::{execution_policy::ExecuteAllways, market_data_policy::MarketDataNull};
+
+    let market = test_market();
+    let mut book = OrderBook::new(market.clone());
+    let stop_order = |order_id, trigger_price| {
+        Rc::new(test_order(
+            &market,
+            1003,
+            order_id,
+            OrderType::Stop(StopOrder {
+                side: Side::Bid,
+                trigger_price,
+                quantity: 100000,
+            }),
+        ))
+    };
+
+    for (order_id, price) in [(1, 10000), (2, 10100), (3, 10200), (4, 11000)] {
+        book.place_order(
+            test_limit_order(&market, 1001, order_id, Side::Ask, price, 100000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    }
+    book.place_order(stop_order(5, 10100), &ExecuteAllways, &MarketDataNull)
+        .unwrap();
+    book.place_order(stop_order(6, 10000), &ExecuteAllways, &MarketDataNull)
+        .unwrap();
+    book.place_order(stop_order(7, 10500), &ExecuteAllways, &MarketDataNull)
+        .unwrap();
+    assert_eq!(book.get_last_price(), None);
+
+    // Trade at 100.00 triggers stop at 100.00, which trades at 101.00 and triggers stop at
+    // 101.00, which trades at 102.00, but that is not enough to trigger stop at 105.00
+    book.place_order(
+        test_limit_order(&market, 1002, 8, Side::Bid, 10000, 100000),
+        &ExecuteAllways,
+        &MarketDataNull,
+    )
+    .unwrap();
+    assert_eq!(book.get_last_price(), Some(10200));
+    for order_id in [1, 2, 3] {
+        assert_eq!(book.get_order_quantity(1001, order_id), None);
+    }
+    assert_eq!(book.get_order_quantity(1001, 4), Some(100000));
+
+    assert_eq!(
+        book.cancel_order(1003, 7, &ExecuteAllways, &MarketDataNull)
+            .unwrap(),
+        100000
+    );
+    assert!(book
+        .cancel_order(1003, 5, &ExecuteAllways, &MarketDataNull)
+        .is_err());
+}
+
+#[test]
+fn test_stop_limit_order() {
+    use crate::{execution_policy::ExecuteAllways, market_data_policy::MarketDataNull};
+
+    let market = test_market();
+    let mut book = OrderBook::new(market.clone());
+
+    book.place_order(
+        test_limit_order(&market, 1001, 1, Side::Bid, 10000, 100000),
+        &ExecuteAllways,
+        &MarketDataNull,
+    )
+    .unwrap();
+    book.place_order(
+        Rc::new(test_order(
+            &market,
+            1003,
+            2,
+            OrderType::StopLimit(StopLimitOrder {
+                side: Side::Ask,
+                trigger_price: 10000,
+                price: 9900,
+                quantity: 300000,
+            }),
+        )),
+        &ExecuteAllways,
+        &MarketDataNull,
+    )
+    .unwrap();
+    assert_eq!(book.get_order_quantity(1003, 2), None);
+
+    // Sell at 100.00 triggers sell stop limit, which sells what's left on the
+    // book down to 99.00 and places the rest
+    book.place_order(
+        test_limit_order(&market, 1002, 3, Side::Ask, 10000, 50000),
+        &ExecuteAllways,
+        &MarketDataNull,
+    )
+    .unwrap();
+    assert_eq!(book.get_order_quantity(1001, 1), None);
+    assert_eq!(book.get_order_quantity(1003, 2), Some(250000));
+}
+
+#[test]
+fn test_fill_or_kill_order() {
+    use crate::{execution_policy::ExecuteAllways, market_data_policy::MarketDataNull};
+
+    let market = test_market();
+    let mut book = OrderBook::new(market.clone());
+    let fill_or_kill_order = |order_id, price, quantity| {
+        Rc::new(test_order(
+            &market,
+            1002,
+            order_id,
+            OrderType::FillOrKill(LimitOrder {
+                side: Side::Bid,
+                price,
+                quantity,
+            }),
+        ))
+    };
+
+    for (order_id, price) in [(1, 10000), (2, 10100), (3, 10200)] {
+        book.place_order(
+            test_limit_order(&market, 1001, order_id, Side::Ask, price, 100000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    }
+
+    // Not enough quantity within limit price, so nothing is executed
+    assert!(book
+        .place_order(
+            fill_or_kill_order(4, 10100, 250000),
+            &ExecuteAllways,
+            &MarketDataNull
+        )
+        .is_err());
+    for order_id in [1, 2, 3] {
+        assert_eq!(book.get_order_quantity(1001, order_id), Some(100000));
+    }
+    assert_eq!(book.get_last_price(), None);
+
+    book.place_order(
+        fill_or_kill_order(5, 10200, 250000),
+        &ExecuteAllways,
+        &MarketDataNull,
+    )
+    .unwrap();
+    assert_eq!(book.get_order_quantity(1001, 1), None);
+    assert_eq!(book.get_order_quantity(1001, 2), None);
+    assert_eq!(book.get_order_quantity(1001, 3), Some(50000));
+    assert_eq!(book.get_order_quantity(1002, 5), None);
+}
+
+#[test]
+fn test_post_only_order() {
+    use crate::{execution_policy::ExecuteAllways, market_data_policy::MarketDataNull};
+
+    let market = test_market();
+    let mut book = OrderBook::new(market.clone());
+    let post_only_order = |order_id, price, reprice| {
+        Rc::new(test_order(
+            &market,
+            1002,
+            order_id,
+            OrderType::PostOnly(PostOnlyOrder {
+                limit: LimitOrder {
+                    side: Side::Bid,
+                    price,
+                    quantity: 100000,
+                },
+                reprice,
+            }),
+        ))
+    };
+
+    book.place_order(
+        test_limit_order(&market, 1001, 1, Side::Ask, 10000, 100000),
+        &ExecuteAllways,
+        &MarketDataNull,
+    )
+    .unwrap();
+
+    book.place_order(
+        post_only_order(2, 9900, false),
+        &ExecuteAllways,
+        &MarketDataNull,
+    )
+    .unwrap();
+    assert_eq!(book.get_best_price(Side::Bid), Some(9900));
+
+    assert!(book
+        .place_order(
+            post_only_order(3, 10000, false),
+            &ExecuteAllways,
+            &MarketDataNull
+        )
+        .is_err());
+    assert_eq!(book.get_order_quantity(1001, 1), Some(100000));
+    assert_eq!(book.get_order_quantity(1002, 3), None);
+
+    book.place_order(
+        post_only_order(4, 10100, true),
+        &ExecuteAllways,
+        &MarketDataNull,
+    )
+    .unwrap();
+    assert_eq!(book.get_order_quantity(1001, 1), Some(100000));
+    assert_eq!(book.get_order_quantity(1002, 4), Some(100000));
+    assert_eq!(book.get_best_price(Side::Bid), Some(9999));
+    assert_eq!(book.get_last_price(), None);
+}
+
+/// Remembers placed and cancelled quantities
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct TrackMarketData {
+    pub(crate) placed: RefCell<Vec<u64>>,
+    pub(crate) cancelled: RefCell<Vec<u64>>,
+}
+
+#[cfg(test)]
+impl MarketDataPolicy for TrackMarketData {
+    fn handle_order_placed(&self, order_quantity: &OrderQuantity) {
+        self.placed.borrow_mut().push(order_quantity.quantity);
+    }
+    fn handle_order_cancelled(&self, order_quantity: &OrderQuantity) {
+        self.cancelled.borrow_mut().push(order_quantity.quantity);
+    }
+    fn handle_order_executed(
+        &self,
+        _executed_quantity: u64,
+        _aggressor_order: &OrderQuantity,
+        _book_order: &OrderQuantity,
+    ) {
+    }
+}
+
+#[test]
+fn test_iceberg_order() {
+    use crate::execution_policy::ExecuteAllways;
+
+    let market = test_market();
+    let mut book = OrderBook::new(market.clone());
+    let market_data = TrackMarketData::default();
+
+    book.place_order(
+        Rc::new(test_order(
+            &market,
+            1001,
+            1,
+            OrderType::Iceberg(IcebergOrder {
+                limit: LimitOrder {
+                    side: Side::Ask,
+                    price: 10000,
+                    quantity: 250000,
+                },
+                display_quantity: 100000,
+            }),
+        )),
+        &ExecuteAllways,
+        &market_data,
+    )
+    .unwrap();
+    book.place_order(
+        test_limit_order(&market, 1002, 2, Side::Ask, 10000, 100000),
+        &ExecuteAllways,
+        &market_data,
+    )
+    .unwrap();
+    assert_eq!(*market_data.placed.borrow(), vec![100000, 100000]);
+    assert_eq!(book.get_order_quantity(1001, 1), Some(250000));
+
+    // Consumed slice is replenished behind the other order
+    book.place_order(
+        test_limit_order(&market, 1003, 3, Side::Bid, 10000, 150000),
+        &ExecuteAllways,
+        &market_data,
+    )
+    .unwrap();
+    assert_eq!(*market_data.placed.borrow(), vec![100000, 100000, 100000]);
+    assert_eq!(book.get_order_quantity(1001, 1), Some(150000));
+    assert_eq!(book.get_order_quantity(1002, 2), Some(50000));
+
+    // Last slice is smaller than display quantity
+    book.place_order(
+        test_limit_order(&market, 1003, 4, Side::Bid, 10000, 150000),
+        &ExecuteAllways,
+        &market_data,
+    )
+    .unwrap();
+    assert_eq!(
+        *market_data.placed.borrow(),
+        vec![100000, 100000, 100000, 50000]
+    );
+    assert_eq!(book.get_order_quantity(1001, 1), Some(50000));
+    assert_eq!(book.get_order_quantity(1002, 2), None);
+
+    book.place_order(
+        test_limit_order(&market, 1003, 5, Side::Bid, 10000, 50000),
+        &ExecuteAllways,
+        &market_data,
+    )
+    .unwrap();
+    assert_eq!(book.get_order_quantity(1001, 1), None);
+    assert_eq!(book.get_best_price(Side::Ask), None);
+}
+
+#[test]
+fn test_quote_market_order() {
+    use crate::{execution_policy::ExecuteAllways, market_data_policy::MarketDataNull};
+
+    let market = test_market();
+    let mut book = OrderBook::new(market.clone());
+    let quote_market_order = |order_id, side, value| {
+        Rc::new(test_order(
+            &market,
+            1002,
+            order_id,
+            OrderType::QuoteMarket(QuoteMarketOrder { side, value }),
+        ))
+    };
+
+    for (order_id, price) in [(1, 10000), (2, 10100)] {
+        book.place_order(
+            test_limit_order(&market, 1001, order_id, Side::Ask, price, 100000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    }
+
+    // 100.00 USDT buys whole first level, and 50.00 USDT buys 0.49504 BTC at 101.00,
+    // which leaves 0.01 USDT unspent
+    let outcome = book
+        .place_order(
+            quote_market_order(3, Side::Bid, 15000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 149504);
+    assert_eq!(outcome.cancelled_value, 1);
+    assert_eq!(outcome.cancel_reason, Some(CancelReason::NoLiquidity));
+    assert_eq!(book.get_order_quantity(1001, 1), None);
+    assert_eq!(book.get_order_quantity(1001, 2), Some(50496));
+    assert_eq!(book.get_last_price(), Some(10100));
+
+    // Value over the whole book buys all, and the rest of value is cancelled
+    let market_data = TrackMarketData::default();
+    let outcome = book
+        .place_order(
+            quote_market_order(5, Side::Bid, 100000),
+            &ExecuteAllways,
+            &market_data,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 50496);
+    assert_eq!(outcome.cancelled_value, 94900);
+    assert_eq!(outcome.cancel_reason, Some(CancelReason::NoLiquidity));
+    assert_eq!(*market_data.cancelled.borrow(), vec![0]);
+    assert_eq!(book.get_best_price(Side::Ask), None);
+
+    book.place_order(
+        test_limit_order(&market, 1001, 6, Side::Bid, 10000, 100000),
+        &ExecuteAllways,
+        &MarketDataNull,
+    )
+    .unwrap();
+    book.place_order(
+        quote_market_order(7, Side::Ask, 2550),
+        &ExecuteAllways,
+        &MarketDataNull,
+    )
+    .unwrap();
+    assert_eq!(book.get_order_quantity(1001, 6), Some(74500));
+
+    // Value smaller than one lot of 0.01 BTC buys nothing
+    let market = Rc::new(Market {
+        multiplier: 1000,
+        ..Rc::into_inner(test_market()).unwrap()
+    });
+    let mut book = OrderBook::new(market.clone());
+    book.place_order(
+        test_limit_order(&market, 1001, 1, Side::Ask, 10100, 100000),
+        &ExecuteAllways,
+        &MarketDataNull,
+    )
+    .unwrap();
+    let outcome = book
+        .place_order(
+            Rc::new(test_order(
+                &market,
+                1002,
+                2,
+                OrderType::QuoteMarket(QuoteMarketOrder {
+                    side: Side::Bid,
+                    value: 100,
+                }),
+            )),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 0);
+    assert_eq!(outcome.cancelled_value, 100);
+    assert_eq!(outcome.cancel_reason, Some(CancelReason::NoLiquidity));
+    assert_eq!(book.get_order_quantity(1001, 1), Some(100000));
+
+    // 25.50 USDT buys 0.25 BTC at 101.00, and 0.25 USDT left is cancelled
+    let outcome = book
+        .place_order(
+            Rc::new(test_order(
+                &market,
+                1002,
+                3,
+                OrderType::QuoteMarket(QuoteMarketOrder {
+                    side: Side::Bid,
+                    value: 2550,
+                }),
+            )),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 25000);
+    assert_eq!(outcome.cancelled_value, 25);
+    assert_eq!(outcome.cancel_reason, Some(CancelReason::NoLiquidity));
+    assert_eq!(book.get_order_quantity(1001, 1), Some(75000));
+}
+
+#[test]
+fn test_immediate_order_outcome() {
+    use crate::execution_policy::ExecuteAllways;
+
+    let market = test_market();
+    let mut book = OrderBook::new(market.clone());
+    let market_data = TrackMarketData::default();
+
+    for (order_id, price) in [(1, 10000), (2, 10100), (3, 10200)] {
+        book.place_order(
+            test_limit_order(&market, 1001, order_id, Side::Ask, price, 100000),
+            &ExecuteAllways,
+            &market_data,
+        )
+        .unwrap();
+    }
+
+    let outcome = book
+        .place_order(
+            Rc::new(test_order(
+                &market,
+                1002,
+                4,
+                OrderType::ImmediateOrCancel(LimitOrder {
+                    side: Side::Bid,
+                    price: 10100,
+                    quantity: 250000,
+                }),
+            )),
+            &ExecuteAllways,
+            &market_data,
+        )
+        .unwrap();
+    assert_eq!(
+        outcome,
+        OrderOutcome {
+            filled_quantity: 200000,
+            average_price: Some(10050),
+            last_price: Some(10100),
+            cancelled_quantity: 50000,
+            cancelled_value: 0,
+            cancel_reason: Some(CancelReason::NoLiquidity),
+            rejected_triggered_orders: vec![],
+        }
+    );
+    assert_eq!(*market_data.cancelled.borrow(), vec![50000]);
+
+    let outcome = book
+        .place_order(
+            Rc::new(test_order(
+                &market,
+                1002,
+                5,
+                OrderType::Market(MarketOrder {
+                    side: Side::Bid,
+                    quantity: 150000,
+                }),
+            )),
+            &ExecuteAllways,
+            &market_data,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 100000);
+    assert_eq!(outcome.average_price, Some(10200));
+    assert_eq!(outcome.cancelled_quantity, 50000);
+    assert_eq!(outcome.cancel_reason, Some(CancelReason::NoLiquidity));
+    assert_eq!(*market_data.cancelled.borrow(), vec![50000, 50000]);
+
+    // Resting remainder of limit order is not cancelled
+    let outcome = book
+        .place_order(
+            test_limit_order(&market, 1002, 6, Side::Bid, 10000, 100000),
+            &ExecuteAllways,
+            &market_data,
+        )
+        .unwrap();
+    assert_eq!(outcome, OrderOutcome::default());
+    assert_eq!(book.get_order_quantity(1002, 6), Some(100000));
+}
+
+#[test]
+fn test_market_order_price_protection() {
+    use crate::{execution_policy::ExecuteAllways, market_data_policy::MarketDataNull};
+
+    let protected_market = |price_protection| {
+        Rc::new(Market {
+            price_protection: Some(price_protection),
+            ..Rc::into_inner(test_market()).unwrap()
+        })
+    };
+    let market_order = |market: &Rc<Market>, order_id, side| {
+        Rc::new(test_order(
+            market,
+            1002,
+            order_id,
+            OrderType::Market(MarketOrder {
+                side,
+                quantity: 400000,
+            }),
+        ))
+    };
+
+    // Band of 1% from 100.00 stops buying at 101.00
+    let market = protected_market(PriceProtection::BasisPoints(100));
+    let mut book = OrderBook::new(market.clone());
+    for (order_id, price) in [(1, 10000), (2, 10050), (3, 10100), (4, 10200)] {
+        book.place_order(
+            test_limit_order(&market, 1001, order_id, Side::Ask, price, 100000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    }
+    let outcome = book
+        .place_order(
+            market_order(&market, 5, Side::Bid),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 300000);
+    assert_eq!(outcome.last_price, Some(10100));
+    assert_eq!(outcome.cancelled_quantity, 100000);
+    assert_eq!(outcome.cancel_reason, Some(CancelReason::PriceProtection));
+    assert_eq!(book.get_order_quantity(1001, 4), Some(100000));
+
+    // Band of 50 ticks from 100.00 stops selling at 99.50
+    let market = protected_market(PriceProtection::Ticks(50));
+    let mut book = OrderBook::new(market.clone());
+    for (order_id, price) in [(1, 10000), (2, 9950), (3, 9900)] {
+        book.place_order(
+            test_limit_order(&market, 1001, order_id, Side::Bid, price, 100000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    }
+    let outcome = book
+        .place_order(
+            market_order(&market, 4, Side::Ask),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 200000);
+    assert_eq!(outcome.last_price, Some(9950));
+    assert_eq!(outcome.cancelled_quantity, 200000);
+    assert_eq!(outcome.cancel_reason, Some(CancelReason::PriceProtection));
+    assert_eq!(book.get_best_price(Side::Bid), Some(9900));
+
+    // Value of quote market order left beyond the band is cancelled
+    book.place_order(
+        test_limit_order(&market, 1001, 5, Side::Bid, 9800, 100000),
+        &ExecuteAllways,
+        &MarketDataNull,
+    )
+    .unwrap();
+    let outcome = book
+        .place_order(
+            Rc::new(test_order(
+                &market,
+                1002,
+                6,
+                OrderType::QuoteMarket(QuoteMarketOrder {
+                    side: Side::Ask,
+                    value: 50000,
+                }),
+            )),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 100000);
+    assert_eq!(outcome.cancelled_value, 40100);
+    assert_eq!(outcome.cancel_reason, Some(CancelReason::PriceProtection));
+    assert_eq!(book.get_best_price(Side::Bid), Some(9800));
+}
+
+#[test]
+fn test_self_trade_prevention() {
+    use crate::{execution_policy::ExecuteAllways, market_data_policy::MarketDataNull};
+
+    let market = test_market();
+    let new_book = || {
+        let mut book = OrderBook::new(market.clone());
+        for (participant_id, order_id) in [(1001, 1), (1002, 2)] {
+            book.place_order(
+                test_limit_order(&market, participant_id, order_id, Side::Ask, 10000, 100000),
+                &ExecuteAllways,
+                &MarketDataNull,
+            )
+            .unwrap();
+        }
+        book
+    };
+    let aggressor_order = |self_trade_prevention, quantity| {
+        Rc::new(Order {
+            self_trade_prevention,
+            ..test_order(
+                &market,
+                1001,
+                3,
+                OrderType::Limit(LimitOrder {
+                    side: Side::Bid,
+                    price: 10000,
+                    quantity,
+                }),
+            )
+        })
+    };
+
+    // Mode: filled, cancelled, resting quantities of orders 1, 2, and 3
+    for (self_trade_prevention, filled, cancelled, resting) in [
+        (
+            SelfTradePrevention::CancelNewest,
+            0,
+            150000,
+            [Some(100000), Some(100000), None],
+        ),
+        (
+            SelfTradePrevention::CancelOldest,
+            100000,
+            0,
+            [None, None, Some(50000)],
+        ),
+        (
+            SelfTradePrevention::CancelBoth,
+            0,
+            150000,
+            [None, Some(100000), None],
+        ),
+        (
+            SelfTradePrevention::DecrementAndCancel,
+            50000,
+            100000,
+            [None, Some(50000), None],
+        ),
+    ] {
+        let mut book = new_book();
+        let outcome = book
+            .place_order(
+                aggressor_order(Some(self_trade_prevention), 150000),
+                &ExecuteAllways,
+                &MarketDataNull,
+            )
+            .unwrap();
+        assert_eq!(outcome.filled_quantity, filled);
+        assert_eq!(outcome.cancelled_quantity, cancelled);
+        assert_eq!(
+            outcome.cancel_reason,
+            (0 < cancelled).then_some(CancelReason::SelfTradePrevention)
+        );
+        assert_eq!(
+            [(1001, 1), (1002, 2), (1001, 3)]
+                .map(|(participant_id, order_id)| book.get_order_quantity(participant_id, order_id)),
+            resting
+        );
+    }
+
+    // Mode of participant decrements larger resting order in place
+    let mut book = new_book();
+    book.set_self_trade_prevention(1001, SelfTradePrevention::DecrementAndCancel);
+    let outcome = book
+        .place_order(
+            aggressor_order(None, 40000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 0);
+    assert_eq!(outcome.cancelled_quantity, 40000);
+    assert_eq!(book.get_order_quantity(1001, 1), Some(60000));
+    assert_eq!(book.get_order_quantity(1002, 2), Some(100000));
+    assert_eq!(book.get_order_quantity(1001, 3), None);
+
+    // Fill-or-Kill order meeting resting order of the same participant is
+    // killed without touching the book, whatever the mode
+    for self_trade_prevention in [
+        SelfTradePrevention::CancelNewest,
+        SelfTradePrevention::CancelOldest,
+        SelfTradePrevention::CancelBoth,
+        SelfTradePrevention::DecrementAndCancel,
+    ] {
+        let mut book = new_book();
+        for quantity in [100000, 200000] {
+            assert_eq!(
+                book.place_order(
+                    Rc::new(Order {
+                        self_trade_prevention: Some(self_trade_prevention),
+                        ..test_order(
+                            &market,
+                            1001,
+                            3,
+                            OrderType::FillOrKill(LimitOrder {
+                                side: Side::Bid,
+                                price: 10000,
+                                quantity,
+                            })
                         )
-                    }
-                }
+                    }),
+                    &ExecuteAllways,
+                    &MarketDataNull,
+                )
+                .map_err(|err| err.kind),
+                Err(BookError::FillOrKillNotFilled.into())
+            );
+        }
+        assert_eq!(book.get_order_quantity(1001, 1), Some(100000));
+        assert_eq!(book.get_order_quantity(1002, 2), Some(100000));
+    }
+}
+
+/// Executes like [`ExecuteAllways`](crate::execution_policy::ExecuteAllways),
+/// except for orders of one participant
+#[cfg(test)]
+struct RejectExecutions {
+    participant_id: usize,
+}
+
+#[cfg(test)]
+impl ExecutionPolicy for RejectExecutions {
+    fn place_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), EngineError> {
+        crate::execution_policy::ExecuteAllways.place_order(order_quantity)
+    }
+    fn cancel_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), EngineError> {
+        crate::execution_policy::ExecuteAllways.cancel_order(order_quantity)
+    }
+    fn execute_orders(
+        &self,
+        executed_quantity: &mut u64,
+        aggressor_order: &mut OrderQuantity,
+        book_order: &mut OrderQuantity,
+    ) -> Result<(), EngineError> {
+        for order_quantity in [&aggressor_order, &book_order] {
+            if order_quantity.order.participant_id == self.participant_id {
+                return Err(EngineError::new(
+                    &order_quantity.order,
+                    crate::error::MarginError::ExecutionBeginFailed,
+                ));
+            }
+        }
+        crate::execution_policy::ExecuteAllways.execute_orders(
+            executed_quantity,
+            aggressor_order,
+            book_order,
+        )
+    }
+    fn check_executions(
+        &self,
+        aggressor_order: &OrderQuantity,
+        executions: &[(u64, OrderQuantity)],
+    ) -> Result<(), EngineError> {
+        for order_quantity in std::iter::once(aggressor_order)
+            .chain(executions.iter().map(|(_, book_order)| book_order))
+        {
+            if order_quantity.order.participant_id == self.participant_id {
+                return Err(EngineError::new(
+                    &order_quantity.order,
+                    crate::error::MarginError::ExecutionBeginFailed,
+                ));
             }
-            _ => Err("Invalid order type".into()),
         }
+        Ok(())
     }
+    fn check_replace_order(
+        &self,
+        replaced_order: &OrderQuantity,
+        order_quantity: &OrderQuantity,
+    ) -> Result<(), EngineError> {
+        crate::execution_policy::ExecuteAllways.check_replace_order(replaced_order, order_quantity)
+    }
+}
+
+#[test]
+fn test_rejected_execution() {
+    let market = test_market();
+    let policy = RejectExecutions {
+        participant_id: 1001,
+    };
+    let new_book = |rejected_execution| {
+        let mut book = OrderBook::new(market.clone());
+        book.set_rejected_execution(rejected_execution);
+        for (participant_id, order_id, price) in
+            [(1001, 1, 10000), (1002, 2, 10000), (1003, 3, 10100)]
+        {
+            book.place_order(
+                test_limit_order(&market, participant_id, order_id, Side::Ask, price, 100),
+                &policy,
+                &crate::market_data_policy::MarketDataNull,
+            )
+            .unwrap();
+        }
+        book
+    };
+
+    // Rejected book order is cancelled, and matching continues
+    let mut book = new_book(RejectedExecution::CancelBookOrder);
+    let market_data = TrackMarketData::default();
+    let outcome = book
+        .place_order(
+            test_limit_order(&market, 1004, 4, Side::Bid, 10100, 150),
+            &policy,
+            &market_data,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 150);
+    assert_eq!(outcome.cancel_reason, None);
+    assert_eq!(*market_data.cancelled.borrow(), vec![100]);
+    assert_eq!(book.get_order_quantity(1001, 1), None);
+    assert_eq!(book.get_order_quantity(1002, 2), None);
+    assert_eq!(book.get_order_quantity(1003, 3), Some(50));
+
+    // Rejected book order is kept at its place in the queue
+    let mut book = new_book(RejectedExecution::KeepBookOrder);
+    let outcome = book
+        .place_order(
+            test_limit_order(&market, 1004, 4, Side::Bid, 10000, 50),
+            &policy,
+            &crate::market_data_policy::MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 50);
+    assert_eq!(book.get_order_quantity(1001, 1), Some(100));
+    assert_eq!(book.get_order_quantity(1002, 2), Some(50));
+    book.cancel_order(1002, 2, &policy, &crate::market_data_policy::MarketDataNull)
+        .unwrap();
+    assert_eq!(book.get_best_price(Side::Ask), Some(10000));
+
+    // Rest of aggressor order is cancelled, rather than rest crossing kept order
+    let mut book = new_book(RejectedExecution::KeepBookOrder);
+    let outcome = book
+        .place_order(
+            test_limit_order(&market, 1004, 4, Side::Bid, 10000, 150),
+            &policy,
+            &crate::market_data_policy::MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 100);
+    assert_eq!(outcome.cancelled_quantity, 50);
+    assert_eq!(outcome.cancel_reason, Some(CancelReason::ExecutionRejected));
+    assert_eq!(book.get_order_quantity(1004, 4), None);
+    assert_eq!(book.get_order_quantity(1001, 1), Some(100));
+    assert_eq!(book.get_best_price(Side::Bid), None);
+
+    // Fill-or-Kill order is killed before any execution, when one of them
+    // would be rejected
+    for rejected_execution in [
+        RejectedExecution::CancelBookOrder,
+        RejectedExecution::KeepBookOrder,
+    ] {
+        let mut book = new_book(rejected_execution);
+        let market_data = TrackMarketData::default();
+        let fill_or_kill_order = Rc::new(test_order(
+            &market,
+            1004,
+            4,
+            OrderType::FillOrKill(LimitOrder {
+                side: Side::Bid,
+                price: 10100,
+                quantity: 150,
+            }),
+        ));
+        assert_eq!(
+            book.place_order(fill_or_kill_order, &policy, &market_data)
+                .map_err(|err| err.kind)
+                .err(),
+            Some(BookError::FillOrKillNotFilled.into())
+        );
+        assert!(market_data.cancelled.borrow().is_empty());
+        assert_eq!(book.get_order_quantity(1001, 1), Some(100));
+        assert_eq!(book.get_order_quantity(1002, 2), Some(100));
+        assert_eq!(book.get_order_quantity(1003, 3), Some(100));
+    }
+
+    // Rest of rejected aggressor order is cancelled
+    let mut book = new_book(RejectedExecution::CancelBookOrder);
+    book.cancel_order(1001, 1, &policy, &crate::market_data_policy::MarketDataNull)
+        .unwrap();
+    let outcome = book
+        .place_order(
+            test_limit_order(&market, 1001, 5, Side::Bid, 10000, 100),
+            &policy,
+            &crate::market_data_policy::MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 0);
+    assert_eq!(outcome.cancelled_quantity, 100);
+    assert_eq!(outcome.cancel_reason, Some(CancelReason::ExecutionRejected));
+    assert_eq!(book.get_order_quantity(1001, 5), None);
+    assert_eq!(book.get_order_quantity(1002, 2), Some(100));
+}
+
+#[test]
+fn test_duplicate_order() {
+    use crate::{execution_policy::ExecuteAllways, market_data_policy::MarketDataNull};
+
+    let market = test_market();
+    let mut book = OrderBook::new(market.clone());
+    book.place_order(
+        test_limit_order(&market, 1001, 1, Side::Ask, 10000, 100000),
+        &ExecuteAllways,
+        &MarketDataNull,
+    )
+    .unwrap();
+
+    // Order with the same ids is rejected before matching
+    assert_eq!(
+        book.place_order(
+            test_limit_order(&market, 1001, 1, Side::Bid, 10000, 50000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .map_err(|err| err.kind)
+        .err(),
+        Some(BookError::DuplicateOrder.into())
+    );
+    assert_eq!(book.get_order_quantity(1001, 1), Some(100000));
+    assert_eq!(book.get_best_price(Side::Bid), None);
+
+    // Same order id of another participant is a different order
+    book.place_order(
+        test_limit_order(&market, 1002, 1, Side::Ask, 10000, 100000),
+        &ExecuteAllways,
+        &MarketDataNull,
+    )
+    .unwrap();
+    assert_eq!(
+        book.cancel_order(1001, 1, &ExecuteAllways, &MarketDataNull),
+        Ok(100000)
+    );
+    assert_eq!(book.get_order_quantity(1002, 1), Some(100000));
 }
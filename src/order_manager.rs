@@ -1,15 +1,32 @@
-use std::{cell::RefCell, collections::HashMap, error::Error, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{btree_map::Entry, BTreeMap, HashMap, HashSet, VecDeque},
+    rc::Rc,
+};
 
 use crate::{
+    clock::Clock,
+    error::{BookError, EngineError, EngineErrorKind, NotFoundError},
     execution_policy::ExecutionPolicy,
+    execution_report::{
+        ExecType, ExecutionReport, ExecutionReportNull, ExecutionReportSink, Liquidity,
+    },
     margin::{MarginLot, MarginLotEventHandler},
     market_data_policy::MarketDataPolicy,
     order::*,
-    order_book::{OrderBook, OrderQuantity},
+    order_book::{OrderBook, OrderOutcome, OrderQuantity},
+    order_state::{OrderState, OrderStatus},
 };
 
+/// Number of finished orders kept in history by default
+pub const ORDER_HISTORY_CAPACITY: usize = 10_000;
+
+/// First of order ids assigned by exchange, which participants cannot choose
+/// themselves
+pub const FIRST_ASSIGNED_ORDER_ID: usize = usize::MAX / 2 + 1;
+
 pub trait OrderBookManager {
-    fn get_order_book(&self, symbol: &String) -> Option<Rc<RefCell<OrderBook>>>;
+    fn get_order_book(&self, symbol: &str) -> Option<Rc<RefCell<OrderBook>>>;
 }
 
 pub struct OrderBooks {
@@ -28,39 +45,1066 @@ impl OrderBooks {
 }
 
 impl OrderBookManager for OrderBooks {
-    fn get_order_book(&self, symbol: &String) -> Option<Rc<RefCell<OrderBook>>> {
+    fn get_order_book(&self, symbol: &str) -> Option<Rc<RefCell<OrderBook>>> {
         let book = self.books.get(symbol);
         book.cloned()
     }
 }
 
+/// Order referred to either by exchange order id or by client order id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderRef {
+    OrderId(usize),
+    ClientOrderId(String),
+}
+
+impl From<usize> for OrderRef {
+    fn from(order_id: usize) -> Self {
+        Self::OrderId(order_id)
+    }
+}
+
+impl From<&str> for OrderRef {
+    fn from(client_order_id: &str) -> Self {
+        Self::ClientOrderId(client_order_id.into())
+    }
+}
+
+impl From<String> for OrderRef {
+    fn from(client_order_id: String) -> Self {
+        Self::ClientOrderId(client_order_id)
+    }
+}
+
+/// Which open orders to cancel at once, where `None` matches any
+#[derive(Debug, Clone, Default)]
+pub struct MassCancelFilter {
+    pub participant_id: Option<usize>,
+    pub symbol: Option<String>,
+    pub side: Option<Side>,
+    pub cancel_on_disconnect: Option<bool>,
+}
+
+impl MassCancelFilter {
+    pub fn matches(&self, order: &Order) -> bool {
+        self.participant_id
+            .is_none_or(|participant_id| participant_id == order.participant_id)
+            && self
+                .symbol
+                .as_ref()
+                .is_none_or(|symbol| *symbol == order.market.symbol)
+            && self.side.is_none_or(|side| Some(side) == order.get_side())
+            && self
+                .cancel_on_disconnect
+                .is_none_or(|cancel_on_disconnect| {
+                    cancel_on_disconnect == order.cancel_on_disconnect
+                })
+    }
+}
+
+/// What was cancelled by mass cancel
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MassCancelSummary {
+    /// Cancelled orders, with quantity cancelled
+    pub cancelled_orders: Vec<((usize, usize), u64)>,
+    /// Errors of orders, which could not be cancelled
+    pub errors: Vec<EngineError>,
+}
+
+impl MassCancelSummary {
+    pub fn get_cancelled_quantity(&self) -> u64 {
+        self.cancelled_orders
+            .iter()
+            .map(|(_, quantity)| quantity)
+            .sum()
+    }
+}
+
+/// Which orders to cancel when participant misses heartbeats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelOnDisconnect {
+    AllOrders,
+    /// Only orders with [`Order::cancel_on_disconnect`] set
+    FlaggedOrders,
+}
+
+struct Heartbeat {
+    /// Time after last heartbeat, when participant is considered disconnected
+    timeout: u64,
+    last_time: u64,
+    cancel_on_disconnect: CancelOnDisconnect,
+}
+
 pub struct OrderManager {
     book_manager: Rc<dyn OrderBookManager>,
-    orders: HashMap<(usize, usize), Rc<Order>>,
+    clock: Rc<dyn Clock>,
+    /// Open orders
+    orders: HashMap<(usize, usize), OrderState>,
+    /// Finished orders, the most recent at the back
+    history: VecDeque<OrderState>,
+    history_capacity: usize,
+    /// Order id of client order id, for open orders and those in history
+    client_order_ids: HashMap<(usize, String), usize>,
+    /// Open orders and those in history, except rejected ones
+    order_ids: HashSet<(usize, usize)>,
+    next_order_id: usize,
+    /// Sibling order of each leg of One-Cancels-Other group
+    oco_orders: HashMap<(usize, usize), usize>,
+    /// Orders with expire time, by expire time
+    expiring_orders: BTreeMap<u64, Vec<(usize, usize)>>,
+    /// Participants expected to send heartbeats
+    heartbeats: HashMap<usize, Heartbeat>,
+    report_sink: Rc<dyn ExecutionReportSink>,
+    next_exec_id: Cell<u64>,
 }
 
 impl OrderManager {
-    pub fn new(book_manager: Rc<dyn OrderBookManager>) -> Self {
+    pub fn new(book_manager: Rc<dyn OrderBookManager>, clock: Rc<dyn Clock>) -> Self {
         Self {
             book_manager,
+            clock,
             orders: HashMap::new(),
+            history: VecDeque::new(),
+            history_capacity: ORDER_HISTORY_CAPACITY,
+            client_order_ids: HashMap::new(),
+            order_ids: HashSet::new(),
+            next_order_id: FIRST_ASSIGNED_ORDER_ID,
+            oco_orders: HashMap::new(),
+            expiring_orders: BTreeMap::new(),
+            heartbeats: HashMap::new(),
+            report_sink: Rc::new(ExecutionReportNull),
+            next_exec_id: Cell::new(1),
         }
     }
 
+    /// Set where to send report of each order state change
+    pub fn set_execution_report_sink(&mut self, report_sink: Rc<dyn ExecutionReportSink>) {
+        self.report_sink = report_sink;
+    }
+
+    /// Assign exchange order id for new order
+    ///
+    /// Assigned ids are unique, and never collide with ids chosen by
+    /// participants, which are rejected unless below [`FIRST_ASSIGNED_ORDER_ID`].
+    pub fn new_order_id(&mut self) -> usize {
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+        order_id
+    }
+
+    /// Set number of finished orders to keep in history
+    pub fn set_history_capacity(&mut self, history_capacity: usize) {
+        self.history_capacity = history_capacity;
+        while history_capacity < self.history.len() {
+            if let Some(state) = self.history.pop_front() {
+                self.release_order_ids(&state);
+            }
+        }
+    }
+
+    /// Get state of open order, or of the most recent finished one
+    pub fn get_order_state(
+        &self,
+        participant_id: usize,
+        order_ref: impl Into<OrderRef>,
+    ) -> Option<&OrderState> {
+        let order_id = self.find_order_id(participant_id, order_ref).ok()?;
+        let key = (participant_id, order_id);
+        self.orders.get(&key).or_else(|| {
+            self.history
+                .iter()
+                .rev()
+                .find(|state| state.get_key() == key)
+        })
+    }
+
+    /// Get open orders of participant, by order id
+    pub fn get_open_orders(&self, participant_id: usize) -> Vec<&OrderState> {
+        let mut orders: Vec<_> = self
+            .orders
+            .values()
+            .filter(|state| state.order.participant_id == participant_id)
+            .collect();
+        orders.sort_by_key(|state| state.order.order_id);
+        orders
+    }
+
     pub fn place_order(
         &mut self,
         order: Rc<Order>,
         execution_policy: &impl ExecutionPolicy,
         market_data_policy: &impl MarketDataPolicy,
-    ) -> Result<(), Box<dyn Error>> {
-        if let Some(book) = self.book_manager.get_order_book(&order.market.symbol) {
+    ) -> Result<OrderOutcome, EngineError> {
+        self.check_order_ids(&order)?;
+        self.place_order_state(
+            OrderState::new(order),
+            ExecType::New,
+            execution_policy,
+            market_data_policy,
+        )
+    }
+
+    /// Check that ids of new order are not taken by another order
+    ///
+    /// Order with taken or unassigned order id is neither reported nor kept
+    /// in history, as both would be keyed by order id of another order.
+    /// Order with taken client order id is rejected.
+    fn check_order_ids(&mut self, order: &Rc<Order>) -> Result<(), EngineError> {
+        // Ids from the first assigned one on are valid only once assigned
+        if self.next_order_id <= order.order_id {
+            return Err(EngineError::new(order, BookError::UnassignedOrderId));
+        }
+        if self
+            .order_ids
+            .contains(&(order.participant_id, order.order_id))
+        {
+            return Err(EngineError::new(order, BookError::DuplicateOrder));
+        }
+        if let Some(client_order_id) = &order.client_order_id {
+            if self
+                .client_order_ids
+                .contains_key(&(order.participant_id, client_order_id.clone()))
+            {
+                let err = EngineError::new(order, BookError::DuplicateClientOrderId);
+                self.reject_order(OrderState::new(order.clone()), &err);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Place order and track its state, starting from the given one, and
+    /// report acceptance of order with given exec type
+    fn place_order_state(
+        &mut self,
+        state: OrderState,
+        exec_type: ExecType,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<OrderOutcome, EngineError> {
+        let order = state.order.clone();
+        let book = match self.get_order_book(&order) {
+            Ok(book) => book,
+            Err(err) => {
+                self.reject_order(state, &err);
+                return Err(err);
+            }
+        };
+
+        let key = state.get_key();
+        if let Some(client_order_id) = &order.client_order_id {
+            self.client_order_ids
+                .insert((key.0, client_order_id.clone()), key.1);
+        }
+        self.order_ids.insert(key);
+        self.orders.insert(key, state);
+        let tracking_policy = TrackOcoExecutions::new(execution_policy, &self.oco_orders);
+        let tracking_market_data = TrackOrderEvents::new(market_data_policy);
+        let result =
             book.borrow_mut()
-                .place_order(order.clone(), execution_policy, market_data_policy)?;
-            self.orders
-                .insert((order.participant_id, order.order_id), order);
-            Ok(())
+                .place_order(order.clone(), &tracking_policy, &tracking_market_data);
+        let mut done_orders = tracking_policy.into_executed_orders();
+        if let (Ok(outcome), Some(state)) = (&result, self.orders.get_mut(&key)) {
+            if state.order.get_quantity().is_none() {
+                // Quantity of order sized by value is known only once matched
+                state.leaves_quantity = outcome.filled_quantity + outcome.cancelled_quantity;
+            }
+            self.send_report(&self.new_report(exec_type, &self.orders[&key]));
+        }
+        self.apply_order_events(tracking_market_data.into_events());
+        let outcome = match result {
+            Ok(outcome) => outcome,
+            Err(err) => {
+                if let Some(state) = self.orders.remove(&key) {
+                    self.release_order_ids(&state);
+                    self.reject_order(state, &err);
+                }
+                return Err(err);
+            }
+        };
+
+        // Triggered orders, which failed, were not placed
+        for err in &outcome.rejected_triggered_orders {
+            if let Some(state) = self.orders.remove(&(err.participant_id, err.order_id)) {
+                self.release_order_ids(&state);
+                self.reject_order(state, err);
+            }
+        }
+        let triggered_orders: Vec<_> = book
+            .borrow()
+            .get_triggered_orders()
+            .iter()
+            .map(|x| (x.participant_id, x.order_id))
+            .collect();
+        for order_key in std::iter::once(key).chain(triggered_orders.iter().copied()) {
+            self.sync_order_state(order_key, &book.borrow());
+        }
+        done_orders.extend(triggered_orders);
+
+        if let Some(expire_time) = order.expire_time {
+            if self.orders.contains_key(&key) {
+                self.expiring_orders
+                    .entry(expire_time)
+                    .or_default()
+                    .push(key);
+            }
+        }
+        self.cancel_oco_siblings(&done_orders, execution_policy, market_data_policy);
+        Ok(outcome)
+    }
+
+    /// Check that order can be placed, and get the book for it
+    fn get_order_book(&self, order: &Order) -> Result<Rc<RefCell<OrderBook>>, EngineError> {
+        order
+            .validate()
+            .map_err(|reason| EngineError::new(order, reason))?;
+        if let Some(expire_time) = order.expire_time {
+            if expire_time <= self.clock.now() {
+                return Err(EngineError::new(order, BookError::OrderExpired));
+            }
+        }
+        self.book_manager
+            .get_order_book(&order.market.symbol)
+            .ok_or_else(|| {
+                EngineError::new(order, NotFoundError::Book(order.market.symbol.clone()))
+            })
+    }
+
+    /// Update states of open orders with executions and cancels
+    fn apply_order_events(&mut self, events: Vec<OrderEvent>) {
+        for (index, event) in events.iter().enumerate() {
+            let key = event.get_key();
+            let Some(state) = self.orders.get_mut(&key) else {
+                continue;
+            };
+            let (exec_type, trade) = match *event {
+                OrderEvent::Executed {
+                    quantity,
+                    price,
+                    liquidity,
+                    ..
+                } => {
+                    state.execute(quantity, price);
+                    // Order sized by value is filled by its last execution,
+                    // unless what is left of it is cancelled afterwards
+                    if state.order.get_quantity().is_none()
+                        && !events[index + 1..]
+                            .iter()
+                            .any(|event| event.get_key() == key)
+                    {
+                        state.close(OrderStatus::Filled);
+                    }
+                    (ExecType::Trade, Some((quantity, price, liquidity)))
+                }
+                OrderEvent::Cancelled { quantity, .. } => {
+                    state.cancel(quantity);
+                    match state.status.is_terminal() {
+                        true => (ExecType::Cancelled, None),
+                        false => (ExecType::Restated, None),
+                    }
+                }
+            };
+            let state = &self.orders[&key];
+            let mut report = self.new_report(exec_type, state);
+            if let Some((quantity, price, liquidity)) = trade {
+                report.last_quantity = quantity;
+                report.last_price = price;
+                report.liquidity = Some(liquidity);
+            }
+            self.send_report(&report);
+            if state.status.is_terminal() {
+                self.finish_order(key, OrderStatus::Cancelled);
+            }
+        }
+    }
+
+    /// Take open quantity of order from the book, and finish order which is
+    /// neither resting nor waiting for its trigger anymore
+    fn sync_order_state(&mut self, key: (usize, usize), book: &OrderBook) {
+        let Some(state) = self.orders.get_mut(&key) else {
+            return;
+        };
+        match book.get_order_quantity(key.0, key.1) {
+            Some(quantity) if quantity != state.leaves_quantity => {
+                state.leaves_quantity = quantity;
+                self.send_report(&self.new_report(ExecType::Restated, &self.orders[&key]));
+            }
+            Some(_) => {}
+            None if !book.contains_order(key.0, key.1) => {
+                self.finish_order(key, OrderStatus::Cancelled);
+            }
+            None => {}
+        }
+    }
+
+    /// Move open order to history with given status, unless it already has
+    /// a terminal one
+    fn finish_order(&mut self, key: (usize, usize), status: OrderStatus) {
+        if let Some(mut state) = self.orders.remove(&key) {
+            self.unschedule_expiry(&state);
+            if !state.status.is_terminal() {
+                state.close(status);
+                let exec_type = match status {
+                    OrderStatus::Expired => ExecType::Expired,
+                    _ => ExecType::Cancelled,
+                };
+                self.send_report(&self.new_report(exec_type, &state));
+            }
+            self.push_history(state);
+        }
+    }
+
+    fn reject_order(&mut self, mut state: OrderState, error: &EngineError) {
+        self.unschedule_expiry(&state);
+        if !state.status.is_terminal() {
+            state.close(OrderStatus::Rejected);
+            let mut report = self.new_report(ExecType::Rejected, &state);
+            report.reject_reason = Some(error.kind.clone());
+            self.send_report(&report);
+        }
+        self.push_history(state);
+    }
+
+    /// Forget expire time of order, which is no longer open
+    fn unschedule_expiry(&mut self, state: &OrderState) {
+        let Some(expire_time) = state.order.expire_time else {
+            return;
+        };
+        if let Entry::Occupied(mut entry) = self.expiring_orders.entry(expire_time) {
+            entry.get_mut().retain(|key| *key != state.get_key());
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+        }
+    }
+
+    fn new_report(&self, exec_type: ExecType, state: &OrderState) -> ExecutionReport {
+        let exec_id = self.next_exec_id.get();
+        self.next_exec_id.set(exec_id + 1);
+        ExecutionReport::new(exec_id, self.clock.now(), exec_type, state)
+    }
+
+    fn send_report(&self, report: &ExecutionReport) {
+        self.report_sink.handle_execution_report(report);
+    }
+
+    fn push_history(&mut self, state: OrderState) {
+        if self.history_capacity == 0 {
+            self.release_order_ids(&state);
+            return;
+        }
+        if self.history.len() == self.history_capacity {
+            if let Some(state) = self.history.pop_front() {
+                self.release_order_ids(&state);
+            }
+        }
+        self.history.push_back(state);
+    }
+
+    /// Let order id and client order id of order be used again, unless they
+    /// are taken by another order
+    ///
+    /// Rejected order released its ids, when it was rejected.
+    fn release_order_ids(&mut self, state: &OrderState) {
+        if state.status == OrderStatus::Rejected {
+            return;
+        }
+        if !self.orders.contains_key(&state.get_key()) {
+            self.order_ids.remove(&state.get_key());
+        }
+        let Some(client_order_id) = &state.order.client_order_id else {
+            return;
+        };
+        let client_key = (state.order.participant_id, client_order_id.clone());
+        if self.client_order_ids.get(&client_key) == Some(&state.order.order_id)
+            && !self.orders.contains_key(&state.get_key())
+        {
+            self.client_order_ids.remove(&client_key);
+        }
+    }
+
+    /// Tell exchange order id of order
+    fn find_order_id(
+        &self,
+        participant_id: usize,
+        order_ref: impl Into<OrderRef>,
+    ) -> Result<usize, EngineError> {
+        match order_ref.into() {
+            OrderRef::OrderId(order_id) => Ok(order_id),
+            OrderRef::ClientOrderId(client_order_id) => self
+                .client_order_ids
+                .get(&(participant_id, client_order_id.clone()))
+                .copied()
+                .ok_or_else(|| {
+                    // Order id is not known, and assigned ids start from 1
+                    EngineError::with_ids(
+                        participant_id,
+                        0,
+                        NotFoundError::ClientOrderId(client_order_id),
+                    )
+                }),
+        }
+    }
+
+    /// Place pair of orders, so that any fill or trigger of one cancels the other
+    ///
+    /// Only one of the legs can rest on the book, and the other must be a stop
+    /// order, which is not promised until triggered. That way margin promise
+    /// is counted only once for the whole group.
+    pub fn place_oco_order(
+        &mut self,
+        first_order: Rc<Order>,
+        second_order: Rc<Order>,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<(), EngineError> {
+        if first_order.participant_id != second_order.participant_id
+            || first_order.order_id == second_order.order_id
+            || first_order.market.symbol != second_order.market.symbol
+        {
+            return Err(EngineError::new(&first_order, BookError::InvalidOcoGroup));
+        }
+        let is_stop = |order: &Order| {
+            matches!(
+                order.order_data,
+                OrderType::Stop(_) | OrderType::StopLimit(_)
+            )
+        };
+        let is_limit = |order: &Order| matches!(order.order_data, OrderType::Limit(_));
+        if !(is_stop(&first_order) && (is_stop(&second_order) || is_limit(&second_order))
+            || is_limit(&first_order) && is_stop(&second_order))
+        {
+            return Err(EngineError::new(&first_order, BookError::InvalidOcoGroup));
+        }
+
+        let participant_id = first_order.participant_id;
+        let first_key = (participant_id, first_order.order_id);
+        let second_key = (participant_id, second_order.order_id);
+        for order in [&first_order, &second_order] {
+            self.check_order_ids(order)?;
+        }
+
+        self.oco_orders.insert(first_key, second_order.order_id);
+        self.oco_orders.insert(second_key, first_order.order_id);
+
+        if let Err(err) = self.place_order(first_order, execution_policy, market_data_policy) {
+            self.oco_orders.remove(&first_key);
+            self.oco_orders.remove(&second_key);
+            return Err(err);
+        }
+        if !self.oco_orders.contains_key(&first_key) {
+            // First order was already executed or triggered
+            return Ok(());
+        }
+        if let Err(err) = self.place_order(second_order, execution_policy, market_data_policy) {
+            self.oco_orders.remove(&second_key);
+            // Participant is told why group was rejected, even if first order
+            // could no longer be cancelled
+            self.cancel_order(
+                participant_id,
+                first_key.1,
+                execution_policy,
+                market_data_policy,
+            )
+            .ok();
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Cancel sibling orders of OCO orders, which were executed or triggered
+    fn cancel_oco_siblings(
+        &mut self,
+        done_orders: &[(usize, usize)],
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) {
+        for (participant_id, order_id) in done_orders {
+            if let Some(sibling_id) = self.oco_orders.remove(&(*participant_id, *order_id)) {
+                self.oco_orders.remove(&(*participant_id, sibling_id));
+                // Sibling may have been executed in the same match
+                let _ = self.cancel_order(
+                    *participant_id,
+                    sibling_id,
+                    execution_policy,
+                    market_data_policy,
+                );
+            }
+        }
+    }
+
+    /// Cancel all orders, which expired by now, and tell which ones were cancelled
+    ///
+    /// Should be called periodically, e.g. on each engine tick.
+    pub fn cancel_expired_orders(
+        &mut self,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Vec<(usize, usize)> {
+        let now = self.clock.now();
+        let mut cancelled_orders = Vec::new();
+        while let Some(entry) = self.expiring_orders.first_entry() {
+            if now < *entry.key() {
+                break;
+            }
+            for (participant_id, order_id) in entry.remove() {
+                // Order could have been cancelled, or amended with a new schedule entry
+                let is_expired = self
+                    .orders
+                    .get(&(participant_id, order_id))
+                    .and_then(|state| state.order.expire_time)
+                    .is_some_and(|expire_time| expire_time <= now);
+                if is_expired
+                    && self
+                        .cancel_order_with_status(
+                            participant_id,
+                            order_id,
+                            OrderStatus::Expired,
+                            execution_policy,
+                            market_data_policy,
+                        )
+                        .is_ok()
+                {
+                    cancelled_orders.push((participant_id, order_id));
+                }
+            }
+        }
+        cancelled_orders
+    }
+
+    /// Cancel all open orders matching the filter, across all books
+    ///
+    /// Orders are cancelled one by one, in order of participant and order id,
+    /// and failure to cancel one does not stop cancelling the others.
+    pub fn mass_cancel(
+        &mut self,
+        filter: &MassCancelFilter,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> MassCancelSummary {
+        let mut keys: Vec<_> = self
+            .orders
+            .values()
+            .filter(|state| filter.matches(&state.order))
+            .map(|state| state.get_key())
+            .collect();
+        keys.sort();
+
+        let mut summary = MassCancelSummary::default();
+        for (participant_id, order_id) in keys {
+            // Sibling of OCO order is cancelled with it
+            if !self.orders.contains_key(&(participant_id, order_id)) {
+                continue;
+            }
+            match self.cancel_order(
+                participant_id,
+                order_id,
+                execution_policy,
+                market_data_policy,
+            ) {
+                Ok(quantity) => summary
+                    .cancelled_orders
+                    .push(((participant_id, order_id), quantity)),
+                Err(err) => summary.errors.push(err),
+            }
+        }
+        summary
+    }
+
+    /// Expect heartbeats from participant at least once per timeout, starting
+    /// from now, or have its orders cancelled
+    pub fn register_heartbeat(
+        &mut self,
+        participant_id: usize,
+        timeout: u64,
+        cancel_on_disconnect: CancelOnDisconnect,
+    ) {
+        self.heartbeats.insert(
+            participant_id,
+            Heartbeat {
+                timeout,
+                last_time: self.clock.now(),
+                cancel_on_disconnect,
+            },
+        );
+    }
+
+    pub fn unregister_heartbeat(&mut self, participant_id: usize) {
+        self.heartbeats.remove(&participant_id);
+    }
+
+    /// Record heartbeat of participant, and tell if participant was registered
+    pub fn handle_heartbeat(&mut self, participant_id: usize) -> bool {
+        let now = self.clock.now();
+        self.heartbeats
+            .get_mut(&participant_id)
+            .map(|heartbeat| heartbeat.last_time = now)
+            .is_some()
+    }
+
+    /// Cancel orders of participants, which missed their heartbeats
+    ///
+    /// Disconnected participant is unregistered, and has to register again.
+    /// Should be called periodically, e.g. on each engine tick.
+    pub fn cancel_disconnected_orders(
+        &mut self,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> MassCancelSummary {
+        let now = self.clock.now();
+        let mut disconnected: Vec<_> = self
+            .heartbeats
+            .iter()
+            .filter(|(_, heartbeat)| heartbeat.last_time.saturating_add(heartbeat.timeout) <= now)
+            .map(|(participant_id, heartbeat)| (*participant_id, heartbeat.cancel_on_disconnect))
+            .collect();
+        disconnected.sort_by_key(|(participant_id, _)| *participant_id);
+
+        let mut summary = MassCancelSummary::default();
+        for (participant_id, cancel_on_disconnect) in disconnected {
+            self.heartbeats.remove(&participant_id);
+            let filter = MassCancelFilter {
+                participant_id: Some(participant_id),
+                cancel_on_disconnect: match cancel_on_disconnect {
+                    CancelOnDisconnect::AllOrders => None,
+                    CancelOnDisconnect::FlaggedOrders => Some(true),
+                },
+                ..Default::default()
+            };
+            let participant_summary =
+                self.mass_cancel(&filter, execution_policy, market_data_policy);
+            summary
+                .cancelled_orders
+                .extend(participant_summary.cancelled_orders);
+            summary.errors.extend(participant_summary.errors);
+        }
+        summary
+    }
+
+    /// Cancel resting order and tell how much quantity was cancelled
+    ///
+    /// Cancelling one order of OCO group cancels the other one as well.
+    pub fn cancel_order(
+        &mut self,
+        participant_id: usize,
+        order_ref: impl Into<OrderRef>,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<u64, EngineError> {
+        let order_id = self.find_order_id(participant_id, order_ref)?;
+        self.cancel_order_with_status(
+            participant_id,
+            order_id,
+            OrderStatus::Cancelled,
+            execution_policy,
+            market_data_policy,
+        )
+    }
+
+    fn cancel_order_with_status(
+        &mut self,
+        participant_id: usize,
+        order_id: usize,
+        status: OrderStatus,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<u64, EngineError> {
+        if let Some(sibling_id) = self.oco_orders.remove(&(participant_id, order_id)) {
+            self.oco_orders.remove(&(participant_id, sibling_id));
+            let _ = self.cancel_order(
+                participant_id,
+                sibling_id,
+                execution_policy,
+                market_data_policy,
+            );
+        }
+
+        let order = &self
+            .orders
+            .get(&(participant_id, order_id))
+            .ok_or_else(|| EngineError::with_ids(participant_id, order_id, NotFoundError::Order))?
+            .order;
+
+        if let Some(book) = self.book_manager.get_order_book(&order.market.symbol) {
+            let quantity = book.borrow_mut().cancel_order(
+                participant_id,
+                order_id,
+                execution_policy,
+                market_data_policy,
+            )?;
+            self.finish_order((participant_id, order_id), status);
+            Ok(quantity)
         } else {
-            Err(format!("Book not found for symbol: {}", order.market.symbol).into())
+            Err(EngineError::new(
+                order,
+                NotFoundError::Book(order.market.symbol.clone()),
+            ))
+        }
+    }
+
+    /// Amend price and/or open quantity of resting order
+    ///
+    /// Quantity decrease at the same price keeps order's place in the queue,
+    /// while price change or quantity increase loses priority and order is
+    /// matched again as an aggressor.
+    pub fn amend_order(
+        &mut self,
+        participant_id: usize,
+        order_ref: impl Into<OrderRef>,
+        price: u64,
+        quantity: u64,
+        execution_policy: &impl ExecutionPolicy,
+        market_data_policy: &impl MarketDataPolicy,
+    ) -> Result<(), EngineError> {
+        let order_id = self.find_order_id(participant_id, order_ref)?;
+        let error = |kind: EngineErrorKind| EngineError::with_ids(participant_id, order_id, kind);
+        if quantity == 0 {
+            return Err(error(BookError::NotEnoughQuantity.into()));
+        }
+
+        let symbol = &self
+            .orders
+            .get(&(participant_id, order_id))
+            .ok_or_else(|| error(NotFoundError::Order.into()))?
+            .order
+            .market
+            .symbol;
+
+        let book = self
+            .book_manager
+            .get_order_book(symbol)
+            .ok_or_else(|| error(NotFoundError::Book(symbol.clone()).into()))?;
+
+        // Order could have been re-priced or triggered, so take it from the book
+        let order = book
+            .borrow()
+            .get_order(participant_id, order_id)
+            .ok_or_else(|| error(NotFoundError::Order.into()))?;
+        let limit = order
+            .get_book_limit()
+            .ok_or_else(|| error(BookError::InvalidOrderType.into()))?;
+        let amended_order = Rc::new(
+            order
+                .new_amended_order(price, quantity)
+                .ok_or_else(|| error(BookError::InvalidOrderType.into()))?,
+        );
+        amended_order
+            .validate()
+            .map_err(|reason| error(reason.into()))?;
+
+        if price == limit.price
+            && book.borrow_mut().reduce_order(
+                participant_id,
+                order_id,
+                quantity,
+                execution_policy,
+                market_data_policy,
+            )?
+        {
+            if let Some(state) = self.orders.get_mut(&(participant_id, order_id)) {
+                state.leaves_quantity = quantity;
+                let state = &self.orders[&(participant_id, order_id)];
+                self.send_report(&self.new_report(ExecType::Replaced, state));
+            }
+            return Ok(());
+        }
+
+        // Order is kept, if amended order could not take its place
+        book.borrow()
+            .check_replace_order(&amended_order, execution_policy)?;
+        book.borrow_mut().cancel_order(
+            participant_id,
+            order_id,
+            execution_policy,
+            market_data_policy,
+        )?;
+        let mut state = self
+            .orders
+            .remove(&(participant_id, order_id))
+            .ok_or_else(|| error(NotFoundError::Order.into()))?;
+        self.unschedule_expiry(&state);
+
+        // Amended order keeps what was executed so far
+        state.order = amended_order;
+        state.leaves_quantity = quantity;
+        self.place_order_state(
+            state,
+            ExecType::Replaced,
+            execution_policy,
+            market_data_policy,
+        )?;
+        Ok(())
+    }
+}
+
+/// Remembers executions of OCO orders
+struct TrackOcoExecutions<'a, T>
+where
+    T: ExecutionPolicy,
+{
+    policy: &'a T,
+    oco_orders: &'a HashMap<(usize, usize), usize>,
+    executed_orders: RefCell<Vec<(usize, usize)>>,
+}
+
+impl<'a, T> TrackOcoExecutions<'a, T>
+where
+    T: ExecutionPolicy,
+{
+    fn new(policy: &'a T, oco_orders: &'a HashMap<(usize, usize), usize>) -> Self {
+        Self {
+            policy,
+            oco_orders,
+            executed_orders: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn into_executed_orders(self) -> Vec<(usize, usize)> {
+        self.executed_orders.into_inner()
+    }
+
+    fn track(&self, order: &Order) {
+        let key = (order.participant_id, order.order_id);
+        if self.oco_orders.contains_key(&key) {
+            self.executed_orders.borrow_mut().push(key);
+        }
+    }
+}
+
+impl<T> ExecutionPolicy for TrackOcoExecutions<'_, T>
+where
+    T: ExecutionPolicy,
+{
+    fn place_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), EngineError> {
+        self.policy.place_order(order_quantity)
+    }
+
+    fn cancel_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), EngineError> {
+        self.policy.cancel_order(order_quantity)
+    }
+
+    fn execute_orders(
+        &self,
+        executed_quantity: &mut u64,
+        aggressor_order: &mut OrderQuantity,
+        book_order: &mut OrderQuantity,
+    ) -> Result<(), EngineError> {
+        self.policy
+            .execute_orders(executed_quantity, aggressor_order, book_order)?;
+        self.track(&aggressor_order.order);
+        self.track(&book_order.order);
+        Ok(())
+    }
+
+    fn check_executions(
+        &self,
+        aggressor_order: &OrderQuantity,
+        executions: &[(u64, OrderQuantity)],
+    ) -> Result<(), EngineError> {
+        self.policy.check_executions(aggressor_order, executions)
+    }
+
+    fn check_replace_order(
+        &self,
+        replaced_order: &OrderQuantity,
+        order_quantity: &OrderQuantity,
+    ) -> Result<(), EngineError> {
+        self.policy
+            .check_replace_order(replaced_order, order_quantity)
+    }
+}
+
+enum OrderEvent {
+    Executed {
+        key: (usize, usize),
+        quantity: u64,
+        price: u64,
+        liquidity: Liquidity,
+    },
+    Cancelled {
+        key: (usize, usize),
+        quantity: u64,
+    },
+}
+
+impl OrderEvent {
+    fn get_key(&self) -> (usize, usize) {
+        match self {
+            Self::Executed { key, .. } | Self::Cancelled { key, .. } => *key,
+        }
+    }
+}
+
+/// Remembers executions and cancels of orders, to update their states
+struct TrackOrderEvents<'a, T>
+where
+    T: MarketDataPolicy,
+{
+    policy: &'a T,
+    events: RefCell<Vec<OrderEvent>>,
+}
+
+impl<'a, T> TrackOrderEvents<'a, T>
+where
+    T: MarketDataPolicy,
+{
+    fn new(policy: &'a T) -> Self {
+        Self {
+            policy,
+            events: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn into_events(self) -> Vec<OrderEvent> {
+        self.events.into_inner()
+    }
+}
+
+impl<T> MarketDataPolicy for TrackOrderEvents<'_, T>
+where
+    T: MarketDataPolicy,
+{
+    fn handle_order_placed(&self, order_quantity: &OrderQuantity) {
+        self.policy.handle_order_placed(order_quantity);
+    }
+
+    fn handle_order_cancelled(&self, order_quantity: &OrderQuantity) {
+        self.policy.handle_order_cancelled(order_quantity);
+        self.events.borrow_mut().push(OrderEvent::Cancelled {
+            key: (
+                order_quantity.order.participant_id,
+                order_quantity.order.order_id,
+            ),
+            quantity: order_quantity.get_total_quantity(),
+        });
+    }
+
+    fn handle_order_executed(
+        &self,
+        executed_quantity: u64,
+        aggressor_order: &OrderQuantity,
+        book_order: &OrderQuantity,
+    ) {
+        self.policy
+            .handle_order_executed(executed_quantity, aggressor_order, book_order);
+        // Orders execute at the price of the book order
+        let Some(limit) = book_order.order.get_book_limit() else {
+            return;
+        };
+        let mut events = self.events.borrow_mut();
+        for (order_quantity, liquidity) in [
+            (aggressor_order, Liquidity::Taker),
+            (book_order, Liquidity::Maker),
+        ] {
+            events.push(OrderEvent::Executed {
+                key: (
+                    order_quantity.order.participant_id,
+                    order_quantity.order.order_id,
+                ),
+                quantity: executed_quantity,
+                price: limit.price,
+                liquidity,
+            });
         }
     }
 }
@@ -89,7 +1133,7 @@ impl<T> ExecutionPolicy for LogExecutions<T>
 where
     T: ExecutionPolicy,
 {
-    fn place_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), Box<dyn Error>> {
+    fn place_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), EngineError> {
         if let Err(err) = self.policy.place_order(order_quantity) {
             println!(
                 "User    <--- Cancel({}):            {:24} <- (Order({}:{}): {}) - Reason: {}",
@@ -113,7 +1157,7 @@ where
             Ok(())
         }
     }
-    fn cancel_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), Box<dyn Error>> {
+    fn cancel_order(&self, order_quantity: &mut OrderQuantity) -> Result<(), EngineError> {
         if let Err(err) = self.policy.cancel_order(order_quantity) {
             println!(
                 "User    <--- Err Cancel({}):        {:24} <- (Order({}:{}): {}) - Reason: {}",
@@ -142,7 +1186,7 @@ where
         executed_quantity: &mut u64,
         aggressor_order: &mut OrderQuantity,
         book_order: &mut OrderQuantity,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), EngineError> {
         if let Err(err) = self
             .policy
             .execute_orders(executed_quantity, aggressor_order, book_order)
@@ -170,6 +1214,21 @@ where
             Ok(())
         }
     }
+    fn check_executions(
+        &self,
+        aggressor_order: &OrderQuantity,
+        executions: &[(u64, OrderQuantity)],
+    ) -> Result<(), EngineError> {
+        self.policy.check_executions(aggressor_order, executions)
+    }
+    fn check_replace_order(
+        &self,
+        replaced_order: &OrderQuantity,
+        order_quantity: &OrderQuantity,
+    ) -> Result<(), EngineError> {
+        self.policy
+            .check_replace_order(replaced_order, order_quantity)
+    }
 }
 
 pub struct LogMarketData<T>
@@ -342,3 +1401,1830 @@ where
             .handle_lot_closed(asset, side, lot, order, price, account_id);
     }
 }
+
+#[cfg(test)]
+fn test_order_manager(market: &Rc<Market>) -> (OrderManager, Rc<RefCell<OrderBook>>) {
+    let book = Rc::new(RefCell::new(OrderBook::new(market.clone())));
+    let order_manager = OrderManager::new(
+        Rc::new(OrderBooks::new(std::slice::from_ref(&book))),
+        Rc::new(crate::clock::ManualClock::default()),
+    );
+    (order_manager, book)
+}
+
+/// Margin manager with accounts of participants funded in both assets of market
+#[cfg(test)]
+fn test_margin_manager(
+    market: &Rc<Market>,
+    participant_ids: &[usize],
+) -> crate::margin::MarginManager<crate::margin::MarginLotEventHandlerNull> {
+    use crate::margin::{test_deposit, MarginManager};
+
+    let mut margin_manager = MarginManager::new(crate::margin::MarginLotEventHandlerNull);
+    for participant_id in participant_ids {
+        let mut account = margin_manager.add_account(*participant_id).borrow_mut();
+        account
+            .add_asset_account(&market.base_asset)
+            .add_asset_account(&market.quote_asset);
+        test_deposit(&mut account, &market.base_asset, 1_000_000_000_000);
+        test_deposit(&mut account, &market.quote_asset, 1_000_000_000_000);
+    }
+    margin_manager
+}
+
+#[test]
+fn test_cancel_order() {
+    use crate::{execution_policy::ExecuteAllways, market_data_policy::MarketDataNull};
+
+    let market = test_market();
+    let (mut order_manager, _) = test_order_manager(&market);
+
+    for (order_id, quantity) in [(1, 100000), (2, 50000)] {
+        order_manager
+            .place_order(
+                test_limit_order(&market, 1001, order_id, Side::Bid, 5000000, quantity),
+                &ExecuteAllways,
+                &MarketDataNull,
+            )
+            .unwrap();
+    }
+
+    let cancelled = order_manager
+        .cancel_order(1001, 1, &ExecuteAllways, &MarketDataNull)
+        .unwrap();
+    assert_eq!(cancelled, 100000);
+    assert!(order_manager
+        .cancel_order(1001, 1, &ExecuteAllways, &MarketDataNull)
+        .is_err());
+
+    // Only the remaining order can be matched
+    order_manager
+        .place_order(
+            test_limit_order(&market, 1002, 3, Side::Ask, 5000000, 100000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert!(order_manager
+        .cancel_order(1001, 2, &ExecuteAllways, &MarketDataNull)
+        .is_err());
+    assert_eq!(
+        order_manager
+            .cancel_order(1002, 3, &ExecuteAllways, &MarketDataNull)
+            .unwrap(),
+        50000
+    );
+}
+
+#[test]
+fn test_cancel_order_releases_promise() {
+    use crate::market_data_policy::MarketDataNull;
+
+    let market = test_market();
+    let (mut order_manager, _) = test_order_manager(&market);
+    let margin_manager = test_margin_manager(&market, &[1001]);
+
+    order_manager
+        .place_order(
+            test_limit_order(&market, 1001, 1, Side::Bid, 5000000, 100000),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap();
+
+    let account = margin_manager.get_participants()[&1001].clone();
+    let open_quantity = |symbol: &str| {
+        let account = account.borrow();
+        let asset_account = account.portfolio[symbol].borrow();
+        (
+            asset_account.received.quantity_open,
+            asset_account.delivered.quantity_open,
+        )
+    };
+    assert_eq!(open_quantity("BTC"), (10000000, 0));
+    assert_eq!(open_quantity("USDT"), (0, 5000000));
+
+    order_manager
+        .cancel_order(1001, 1, &margin_manager, &MarketDataNull)
+        .unwrap();
+    assert_eq!(open_quantity("BTC"), (0, 0));
+    assert_eq!(open_quantity("USDT"), (0, 0));
+}
+
+#[test]
+fn test_amend_order_priority() {
+    use crate::{execution_policy::ExecuteAllways, market_data_policy::MarketDataNull};
+
+    let market = test_market();
+    let (mut order_manager, _) = test_order_manager(&market);
+    let place_order = |order_manager: &mut OrderManager, order| {
+        order_manager
+            .place_order(order, &ExecuteAllways, &MarketDataNull)
+            .unwrap()
+    };
+
+    // Quantity decrease keeps priority
+    place_order(
+        &mut order_manager,
+        test_limit_order(&market, 1001, 1, Side::Bid, 5000000, 100000),
+    );
+    place_order(
+        &mut order_manager,
+        test_limit_order(&market, 1001, 2, Side::Bid, 5000000, 100000),
+    );
+    order_manager
+        .amend_order(1001, 1, 5000000, 40000, &ExecuteAllways, &MarketDataNull)
+        .unwrap();
+    place_order(
+        &mut order_manager,
+        test_limit_order(&market, 1002, 3, Side::Ask, 5000000, 50000),
+    );
+    assert!(order_manager
+        .cancel_order(1001, 1, &ExecuteAllways, &MarketDataNull)
+        .is_err());
+
+    // Quantity increase loses priority
+    place_order(
+        &mut order_manager,
+        test_limit_order(&market, 1001, 4, Side::Bid, 5000000, 100000),
+    );
+    order_manager
+        .amend_order(1001, 2, 5000000, 150000, &ExecuteAllways, &MarketDataNull)
+        .unwrap();
+    place_order(
+        &mut order_manager,
+        test_limit_order(&market, 1002, 5, Side::Ask, 5000000, 50000),
+    );
+    assert_eq!(
+        order_manager
+            .cancel_order(1001, 4, &ExecuteAllways, &MarketDataNull)
+            .unwrap(),
+        50000
+    );
+    assert_eq!(
+        order_manager
+            .cancel_order(1001, 2, &ExecuteAllways, &MarketDataNull)
+            .unwrap(),
+        150000
+    );
+
+    // Price change re-matches as aggressor
+    place_order(
+        &mut order_manager,
+        test_limit_order(&market, 1002, 6, Side::Ask, 5100000, 100000),
+    );
+    place_order(
+        &mut order_manager,
+        test_limit_order(&market, 1001, 7, Side::Bid, 5000000, 30000),
+    );
+    order_manager
+        .amend_order(1001, 7, 5100000, 30000, &ExecuteAllways, &MarketDataNull)
+        .unwrap();
+    assert!(order_manager
+        .cancel_order(1001, 7, &ExecuteAllways, &MarketDataNull)
+        .is_err());
+    assert_eq!(
+        order_manager
+            .cancel_order(1002, 6, &ExecuteAllways, &MarketDataNull)
+            .unwrap(),
+        70000
+    );
+}
+
+#[test]
+fn test_amend_order_adjusts_promise() {
+    use crate::market_data_policy::MarketDataNull;
+
+    let market = test_market();
+    let (mut order_manager, _) = test_order_manager(&market);
+    let margin_manager = test_margin_manager(&market, &[1001]);
+
+    order_manager
+        .place_order(
+            test_limit_order(&market, 1001, 1, Side::Ask, 5000000, 100000),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap();
+
+    let account = margin_manager.get_participants()[&1001].clone();
+    let open_quantity = |symbol: &str| {
+        let account = account.borrow();
+        let asset_account = account.portfolio[symbol].borrow();
+        (
+            asset_account.received.quantity_open,
+            asset_account.delivered.quantity_open,
+        )
+    };
+
+    order_manager
+        .amend_order(1001, 1, 5000000, 40000, &margin_manager, &MarketDataNull)
+        .unwrap();
+    assert_eq!(open_quantity("BTC"), (0, 4000000));
+    assert_eq!(open_quantity("USDT"), (2000000, 0));
+
+    order_manager
+        .amend_order(1001, 1, 6000000, 50000, &margin_manager, &MarketDataNull)
+        .unwrap();
+    assert_eq!(open_quantity("BTC"), (0, 5000000));
+    assert_eq!(open_quantity("USDT"), (3000000, 0));
+}
+
+#[test]
+fn test_amend_order_kept_on_failure() {
+    use crate::{
+        error::MarginError, margin::test_deposit, market_data_policy::MarketDataNull,
+        order::PostOnlyOrder, order_state::OrderStatus,
+    };
+
+    let market = test_market();
+    let (mut order_manager, book) = test_order_manager(&market);
+    let mut margin_manager = test_margin_manager(&market, &[1001]);
+    {
+        let mut account = margin_manager.add_account(1002).borrow_mut();
+        account
+            .add_asset_account(&market.base_asset)
+            .add_asset_account(&market.quote_asset);
+        test_deposit(&mut account, &market.base_asset, 10000000);
+    }
+
+    // Balance is enough only for the original quantity
+    order_manager
+        .place_order(
+            test_limit_order(&market, 1002, 1, Side::Ask, 5000000, 100000),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(
+        order_manager
+            .amend_order(1002, 1, 5000000, 150000, &margin_manager, &MarketDataNull)
+            .map_err(|err| err.kind),
+        Err(MarginError::InsufficientBalance("BTC".into()).into())
+    );
+    assert_eq!(book.borrow().get_order_quantity(1002, 1), Some(100000));
+    assert_eq!(
+        order_manager.get_order_state(1002, 1).unwrap().status,
+        OrderStatus::New
+    );
+    let account = margin_manager.get_participants()[&1002].clone();
+    assert_eq!(
+        account.borrow().portfolio["BTC"]
+            .borrow()
+            .delivered
+            .quantity_open,
+        10000000
+    );
+
+    // Post-only order is kept, when amended price would cross
+    order_manager
+        .place_order(
+            Rc::new(test_order(
+                &market,
+                1001,
+                2,
+                OrderType::PostOnly(PostOnlyOrder {
+                    limit: LimitOrder {
+                        side: Side::Bid,
+                        price: 4900000,
+                        quantity: 100000,
+                    },
+                    reprice: false,
+                }),
+            )),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(
+        order_manager
+            .amend_order(1001, 2, 5000000, 100000, &margin_manager, &MarketDataNull)
+            .map_err(|err| err.kind),
+        Err(BookError::PostOnlyWouldCross.into())
+    );
+    assert_eq!(book.borrow().get_order_quantity(1001, 2), Some(100000));
+}
+
+#[cfg(test)]
+fn test_stop_order(
+    market: &Rc<Market>,
+    participant_id: usize,
+    order_id: usize,
+    side: Side,
+    trigger_price: u64,
+    quantity: u64,
+) -> Rc<Order> {
+    Rc::new(test_order(
+        market,
+        participant_id,
+        order_id,
+        OrderType::Stop(StopOrder {
+            side,
+            trigger_price,
+            quantity,
+        }),
+    ))
+}
+
+#[test]
+fn test_oco_order() {
+    use crate::{execution_policy::ExecuteAllways, market_data_policy::MarketDataNull};
+
+    let market = test_market();
+    let (mut order_manager, book) = test_order_manager(&market);
+    let place_oco_order = |order_manager: &mut OrderManager, take_profit_id, stop_loss_id| {
+        order_manager
+            .place_oco_order(
+                test_limit_order(&market, 1001, take_profit_id, Side::Ask, 11000, 100000),
+                test_stop_order(&market, 1001, stop_loss_id, Side::Ask, 9000, 100000),
+                &ExecuteAllways,
+                &MarketDataNull,
+            )
+            .unwrap()
+    };
+
+    // Fill of take profit cancels stop loss
+    place_oco_order(&mut order_manager, 1, 2);
+    order_manager
+        .place_order(
+            test_limit_order(&market, 1002, 3, Side::Bid, 11000, 40000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert!(order_manager
+        .cancel_order(1001, 2, &ExecuteAllways, &MarketDataNull)
+        .is_err());
+    assert_eq!(
+        order_manager
+            .cancel_order(1001, 1, &ExecuteAllways, &MarketDataNull)
+            .unwrap(),
+        60000
+    );
+
+    // Trigger of stop loss cancels take profit
+    place_oco_order(&mut order_manager, 4, 5);
+    order_manager
+        .place_order(
+            test_limit_order(&market, 1002, 6, Side::Bid, 9000, 200000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    order_manager
+        .place_order(
+            test_limit_order(&market, 1003, 7, Side::Ask, 9000, 100000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(book.borrow().get_order_quantity(1001, 4), None);
+    assert_eq!(book.borrow().get_order_quantity(1002, 6), None);
+
+    // Cancel of one order cancels the other
+    order_manager
+        .place_oco_order(
+            test_limit_order(&market, 1001, 8, Side::Ask, 11000, 100000),
+            test_stop_order(&market, 1001, 9, Side::Ask, 8000, 100000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(book.borrow().get_order_quantity(1001, 8), Some(100000));
+    order_manager
+        .cancel_order(1001, 9, &ExecuteAllways, &MarketDataNull)
+        .unwrap();
+    assert_eq!(book.borrow().get_order_quantity(1001, 8), None);
+
+    // Group with taken order id is refused like a single order
+    let place_oco_order = |order_manager: &mut OrderManager, take_profit, stop_loss| {
+        order_manager
+            .place_oco_order(take_profit, stop_loss, &ExecuteAllways, &MarketDataNull)
+            .map_err(|err| err.kind)
+    };
+    assert_eq!(
+        place_oco_order(
+            &mut order_manager,
+            test_limit_order(&market, 1001, 10, Side::Ask, 11000, 100000),
+            test_stop_order(&market, 1001, 8, Side::Ask, 8000, 100000),
+        ),
+        Err(BookError::DuplicateOrder.into())
+    );
+    assert_eq!(book.borrow().get_order_quantity(1001, 10), None);
+
+    // Failure of second order is returned, and first order is cancelled
+    let client_order = |order_id, order_data| {
+        Rc::new(Order {
+            client_order_id: Some("a".into()),
+            ..test_order(&market, 1001, order_id, order_data)
+        })
+    };
+    assert_eq!(
+        place_oco_order(
+            &mut order_manager,
+            client_order(
+                10,
+                OrderType::Limit(LimitOrder {
+                    side: Side::Ask,
+                    price: 11000,
+                    quantity: 100000,
+                }),
+            ),
+            client_order(
+                11,
+                OrderType::Stop(StopOrder {
+                    side: Side::Ask,
+                    trigger_price: 8000,
+                    quantity: 100000,
+                }),
+            ),
+        ),
+        Err(BookError::DuplicateClientOrderId.into())
+    );
+    assert_eq!(book.borrow().get_order_quantity(1001, 10), None);
+}
+
+#[test]
+fn test_oco_order_promised_once() {
+    use crate::market_data_policy::MarketDataNull;
+
+    let market = test_market();
+    let (mut order_manager, _) = test_order_manager(&market);
+    let margin_manager = test_margin_manager(&market, &[1001]);
+
+    order_manager
+        .place_oco_order(
+            test_limit_order(&market, 1001, 1, Side::Ask, 11000, 100000),
+            test_stop_order(&market, 1001, 2, Side::Ask, 9000, 100000),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap();
+
+    let account = margin_manager.get_participants()[&1001].clone();
+    let delivery_promise = || {
+        account.borrow().portfolio["BTC"]
+            .borrow()
+            .delivered
+            .quantity_open
+    };
+    assert_eq!(delivery_promise(), 10000000);
+
+    order_manager
+        .cancel_order(1001, 2, &margin_manager, &MarketDataNull)
+        .unwrap();
+    assert_eq!(delivery_promise(), 0);
+}
+
+#[test]
+fn test_triggered_order_rejected() {
+    use crate::{error::MarginError, market_data_policy::MarketDataNull, order_state::OrderStatus};
+
+    let market = test_market();
+    let (mut order_manager, book) = test_order_manager(&market);
+    let mut margin_manager = test_margin_manager(&market, &[1001, 1002]);
+    margin_manager
+        .add_account(1003)
+        .borrow_mut()
+        .add_asset_account(&market.base_asset)
+        .add_asset_account(&market.quote_asset);
+
+    // Stop order is not promised until triggered, so nothing is checked yet
+    order_manager
+        .place_order(
+            Rc::new(test_order(
+                &market,
+                1003,
+                1,
+                OrderType::StopLimit(StopLimitOrder {
+                    side: Side::Bid,
+                    trigger_price: 5000000,
+                    price: 4900000,
+                    quantity: 100000,
+                }),
+            )),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap();
+
+    order_manager
+        .place_order(
+            test_limit_order(&market, 1002, 2, Side::Ask, 5000000, 100000),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap();
+    let outcome = order_manager
+        .place_order(
+            test_limit_order(&market, 1001, 3, Side::Bid, 5000000, 100000),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 100000);
+    assert_eq!(
+        outcome.rejected_triggered_orders,
+        vec![EngineError::with_ids(
+            1003,
+            1,
+            MarginError::InsufficientBalance("USDT".into())
+        )]
+    );
+    assert_eq!(
+        order_manager.get_order_state(1003, 1).unwrap().status,
+        OrderStatus::Rejected
+    );
+    assert!(!book.borrow().contains_order(1003, 1));
+}
+
+#[test]
+fn test_cancel_expired_orders() {
+    use crate::{clock::ManualClock, market_data_policy::MarketDataNull};
+
+    let market = test_market();
+    let book = Rc::new(RefCell::new(OrderBook::new(market.clone())));
+    let clock = Rc::new(ManualClock::new(1000));
+    let mut order_manager = OrderManager::new(
+        Rc::new(OrderBooks::new(std::slice::from_ref(&book))),
+        clock.clone(),
+    );
+    let margin_manager = test_margin_manager(&market, &[1001]);
+    let expiring_order = |order_id, expire_time| {
+        Rc::new(Order {
+            expire_time,
+            ..test_order(
+                &market,
+                1001,
+                order_id,
+                OrderType::Limit(LimitOrder {
+                    side: Side::Bid,
+                    price: 5000000,
+                    quantity: 100000,
+                }),
+            )
+        })
+    };
+
+    assert!(order_manager
+        .place_order(
+            expiring_order(1, Some(1000)),
+            &margin_manager,
+            &MarketDataNull
+        )
+        .is_err());
+    for (order_id, expire_time) in [(2, Some(2000)), (3, Some(3000)), (4, None)] {
+        order_manager
+            .place_order(
+                expiring_order(order_id, expire_time),
+                &margin_manager,
+                &MarketDataNull,
+            )
+            .unwrap();
+    }
+    let account = margin_manager.get_participants()[&1001].clone();
+    let open_value = || {
+        account.borrow().portfolio["USDT"]
+            .borrow()
+            .delivered
+            .quantity_open
+    };
+    assert_eq!(open_value(), 15000000);
+
+    clock.set_time(1999);
+    assert!(order_manager
+        .cancel_expired_orders(&margin_manager, &MarketDataNull)
+        .is_empty());
+
+    clock.advance(1);
+    assert_eq!(
+        order_manager.cancel_expired_orders(&margin_manager, &MarketDataNull),
+        vec![(1001, 2)]
+    );
+    assert_eq!(book.borrow().get_order_quantity(1001, 2), None);
+    assert_eq!(
+        order_manager.get_order_state(1001, 2).unwrap().status,
+        OrderStatus::Expired
+    );
+    assert_eq!(open_value(), 10000000);
+
+    // Amended order keeps its expire time
+    order_manager
+        .amend_order(1001, 3, 4900000, 100000, &margin_manager, &MarketDataNull)
+        .unwrap();
+    assert_eq!(
+        order_manager.expiring_orders,
+        BTreeMap::from([(3000, vec![(1001, 3)])])
+    );
+    clock.advance(5000);
+    assert_eq!(
+        order_manager.cancel_expired_orders(&margin_manager, &MarketDataNull),
+        vec![(1001, 3)]
+    );
+    assert_eq!(book.borrow().get_order_quantity(1001, 3), None);
+    assert_eq!(book.borrow().get_order_quantity(1001, 4), Some(100000));
+    assert_eq!(open_value(), 5000000);
+
+    // Expire time of cancelled order is forgotten
+    order_manager
+        .place_order(
+            expiring_order(5, Some(9000)),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap();
+    order_manager
+        .cancel_order(1001, 5, &margin_manager, &MarketDataNull)
+        .unwrap();
+    assert!(order_manager.expiring_orders.is_empty());
+}
+
+#[test]
+fn test_order_validation() {
+    use crate::{execution_policy::ExecuteAllways, market_data_policy::MarketDataNull};
+
+    let market = Rc::new(Market {
+        tick: 5,
+        multiplier: 10,
+        order_limits: OrderLimits {
+            min_quantity: Some(1000),
+            max_quantity: Some(1000000),
+            min_notional: Some(1000),
+            max_notional: Some(500000),
+        },
+        ..Rc::into_inner(test_market()).unwrap()
+    });
+    let (mut order_manager, _) = test_order_manager(&market);
+    let mut place_order = |order_id, price, quantity| {
+        order_manager
+            .place_order(
+                test_limit_order(&market, 1001, order_id, Side::Bid, price, quantity),
+                &ExecuteAllways,
+                &MarketDataNull,
+            )
+            .map_err(|err| err.kind)
+            .map(|_| ())
+    };
+
+    assert_eq!(place_order(1, 10000, 100000), Ok(()));
+    assert_eq!(
+        place_order(2, 10001, 100000),
+        Err(EngineErrorKind::Validation(OrderRejectReason::InvalidPrice))
+    );
+    assert_eq!(
+        place_order(3, 0, 100000),
+        Err(EngineErrorKind::Validation(OrderRejectReason::InvalidPrice))
+    );
+    assert_eq!(
+        place_order(4, 10000, 100005),
+        Err(EngineErrorKind::Validation(
+            OrderRejectReason::InvalidLotSize
+        ))
+    );
+    assert_eq!(
+        place_order(5, 10000, 990),
+        Err(EngineErrorKind::Validation(
+            OrderRejectReason::QuantityBelowMinimum
+        ))
+    );
+    assert_eq!(
+        place_order(6, 10000, 1000010),
+        Err(EngineErrorKind::Validation(
+            OrderRejectReason::QuantityAboveMaximum
+        ))
+    );
+    assert_eq!(
+        place_order(7, 50, 1000000),
+        Err(EngineErrorKind::Validation(
+            OrderRejectReason::NotionalBelowMinimum
+        ))
+    );
+    assert_eq!(
+        place_order(8, 100000, 1000000),
+        Err(EngineErrorKind::Validation(
+            OrderRejectReason::NotionalAboveMaximum
+        ))
+    );
+
+    // Amended order is validated too
+    assert_eq!(
+        order_manager
+            .amend_order(1001, 1, 10000, 100005, &ExecuteAllways, &MarketDataNull)
+            .map_err(|err| err.kind),
+        Err(EngineErrorKind::Validation(
+            OrderRejectReason::InvalidLotSize
+        ))
+    );
+
+    // Iceberg order displays some, but not more than all of its quantity
+    let mut place_iceberg_order = |order_id, display_quantity| {
+        order_manager
+            .place_order(
+                Rc::new(test_order(
+                    &market,
+                    1001,
+                    order_id,
+                    OrderType::Iceberg(IcebergOrder {
+                        limit: LimitOrder {
+                            side: Side::Bid,
+                            price: 10000,
+                            quantity: 100000,
+                        },
+                        display_quantity,
+                    }),
+                )),
+                &ExecuteAllways,
+                &MarketDataNull,
+            )
+            .map_err(|err| err.kind)
+            .map(|_| ())
+    };
+    for (order_id, display_quantity) in [(9, 0), (10, 100010)] {
+        assert_eq!(
+            place_iceberg_order(order_id, display_quantity),
+            Err(EngineErrorKind::Validation(
+                OrderRejectReason::InvalidDisplayQuantity
+            ))
+        );
+    }
+    assert_eq!(place_iceberg_order(11, 100000), Ok(()));
+}
+
+#[test]
+fn test_engine_errors() {
+    use crate::{
+        error::MarginError, execution_policy::ExecuteAllways, margin::MarginManager,
+        market_data_policy::MarketDataNull,
+    };
+
+    let market = test_market();
+    let (mut order_manager, _) = test_order_manager(&market);
+
+    assert_eq!(
+        order_manager.cancel_order(1001, 1, &ExecuteAllways, &MarketDataNull),
+        Err(EngineError::with_ids(1001, 1, NotFoundError::Order))
+    );
+
+    // Participant without margin account
+    let margin_manager = MarginManager::new(crate::margin::MarginLotEventHandlerNull);
+    let err = order_manager
+        .place_order(
+            test_limit_order(&market, 1001, 2, Side::Bid, 10000, 100000),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap_err();
+    assert_eq!(
+        err,
+        EngineError::with_ids(1001, 2, MarginError::AccountNotFound)
+    );
+    assert_eq!(err.to_string(), "Margin not found (order 1001:2)");
+}
+
+#[test]
+fn test_order_status() {
+    use crate::{execution_policy::ExecuteAllways, market_data_policy::MarketDataNull};
+
+    let market = test_market();
+    let (mut order_manager, _) = test_order_manager(&market);
+    let status = |order_manager: &OrderManager, participant_id, order_id| {
+        let state = order_manager
+            .get_order_state(participant_id, order_id)
+            .unwrap();
+        (
+            state.status,
+            state.filled_quantity,
+            state.get_average_price(),
+            state.leaves_quantity,
+        )
+    };
+
+    for (order_id, price) in [(1, 5000000), (2, 4900000)] {
+        order_manager
+            .place_order(
+                test_limit_order(&market, 1001, order_id, Side::Bid, price, 100000),
+                &ExecuteAllways,
+                &MarketDataNull,
+            )
+            .unwrap();
+    }
+    assert_eq!(
+        status(&order_manager, 1001, 1),
+        (OrderStatus::New, 0, None, 100000)
+    );
+
+    order_manager
+        .place_order(
+            test_limit_order(&market, 1002, 3, Side::Ask, 4900000, 150000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(
+        status(&order_manager, 1001, 1),
+        (OrderStatus::Filled, 100000, Some(5000000), 0)
+    );
+    assert_eq!(
+        status(&order_manager, 1001, 2),
+        (OrderStatus::PartiallyFilled, 50000, Some(4900000), 50000)
+    );
+    assert_eq!(
+        status(&order_manager, 1002, 3),
+        (OrderStatus::Filled, 150000, Some(4966666), 0)
+    );
+
+    // Remainder of market order is cancelled
+    order_manager
+        .place_order(
+            Rc::new(test_order(
+                &market,
+                1002,
+                4,
+                OrderType::Market(MarketOrder {
+                    side: Side::Ask,
+                    quantity: 80000,
+                }),
+            )),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(
+        status(&order_manager, 1002, 4),
+        (OrderStatus::Cancelled, 50000, Some(4900000), 0)
+    );
+    assert_eq!(
+        status(&order_manager, 1001, 2),
+        (OrderStatus::Filled, 100000, Some(4900000), 0)
+    );
+
+    // Order sized by value is filled, unless some of its value is left
+    for (order_id, price) in [(11, 4900000), (12, 5000000)] {
+        order_manager
+            .place_order(
+                test_limit_order(&market, 1001, order_id, Side::Ask, price, 100000),
+                &ExecuteAllways,
+                &MarketDataNull,
+            )
+            .unwrap();
+    }
+    let quote_market_order = |order_id, value| {
+        Rc::new(test_order(
+            &market,
+            1002,
+            order_id,
+            OrderType::QuoteMarket(QuoteMarketOrder {
+                side: Side::Bid,
+                value,
+            }),
+        ))
+    };
+    for (order_id, value) in [(13, 7400000), (14, 5000000)] {
+        order_manager
+            .place_order(
+                quote_market_order(order_id, value),
+                &ExecuteAllways,
+                &MarketDataNull,
+            )
+            .unwrap();
+    }
+    assert_eq!(
+        status(&order_manager, 1002, 13),
+        (OrderStatus::Filled, 150000, Some(4933333), 0)
+    );
+    assert_eq!(
+        status(&order_manager, 1002, 14),
+        (OrderStatus::Cancelled, 50000, Some(5000000), 0)
+    );
+
+    assert!(order_manager
+        .place_order(
+            test_limit_order(&market, 1001, 5, Side::Bid, 0, 100000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .is_err());
+    assert_eq!(
+        status(&order_manager, 1001, 5),
+        (OrderStatus::Rejected, 0, None, 0)
+    );
+
+    for order in [
+        test_limit_order(&market, 1001, 6, Side::Bid, 4800000, 100000),
+        test_limit_order(&market, 1001, 7, Side::Bid, 4700000, 100000),
+        test_stop_order(&market, 1001, 8, Side::Bid, 5100000, 100000),
+    ] {
+        order_manager
+            .place_order(order, &ExecuteAllways, &MarketDataNull)
+            .unwrap();
+    }
+    order_manager
+        .cancel_order(1001, 6, &ExecuteAllways, &MarketDataNull)
+        .unwrap();
+    assert_eq!(
+        status(&order_manager, 1001, 6),
+        (OrderStatus::Cancelled, 0, None, 0)
+    );
+    assert_eq!(
+        order_manager
+            .get_open_orders(1001)
+            .iter()
+            .map(|state| (state.order.order_id, state.status))
+            .collect::<Vec<_>>(),
+        vec![(7, OrderStatus::New), (8, OrderStatus::New)]
+    );
+    assert!(order_manager.get_open_orders(1002).is_empty());
+
+    // Only the most recent finished orders are kept
+    order_manager.set_history_capacity(2);
+    assert!(order_manager.get_order_state(1001, 1).is_none());
+    assert!(order_manager.get_order_state(1001, 5).is_some());
+    assert!(order_manager.get_order_state(1001, 6).is_some());
+    assert!(order_manager.get_order_state(1001, 7).is_some());
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct TrackExecutionReports {
+    reports: RefCell<Vec<ExecutionReport>>,
+}
+
+#[cfg(test)]
+impl ExecutionReportSink for TrackExecutionReports {
+    fn handle_execution_report(&self, report: &ExecutionReport) {
+        self.reports.borrow_mut().push(report.clone());
+    }
+}
+
+#[test]
+fn test_execution_reports() {
+    use crate::{execution_policy::ExecuteAllways, market_data_policy::MarketDataNull};
+
+    let market = test_market();
+    let (mut order_manager, _) = test_order_manager(&market);
+    let sink = Rc::new(TrackExecutionReports::default());
+    order_manager.set_execution_report_sink(sink.clone());
+    let take_reports = || {
+        sink.reports
+            .take()
+            .into_iter()
+            .map(|report| {
+                (
+                    report.exec_id,
+                    (report.participant_id, report.order_id),
+                    report.exec_type,
+                    report.order_status,
+                    (report.last_quantity, report.last_price, report.liquidity),
+                    (report.filled_quantity, report.leaves_quantity),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    order_manager
+        .place_order(
+            test_limit_order(&market, 1001, 1, Side::Bid, 5000000, 100000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(
+        take_reports(),
+        vec![(
+            1,
+            (1001, 1),
+            ExecType::New,
+            OrderStatus::New,
+            (0, 0, None),
+            (0, 100000)
+        )]
+    );
+
+    order_manager
+        .place_order(
+            test_limit_order(&market, 1002, 2, Side::Ask, 5000000, 40000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(
+        take_reports(),
+        vec![
+            (
+                2,
+                (1002, 2),
+                ExecType::New,
+                OrderStatus::New,
+                (0, 0, None),
+                (0, 40000)
+            ),
+            (
+                3,
+                (1002, 2),
+                ExecType::Trade,
+                OrderStatus::Filled,
+                (40000, 5000000, Some(Liquidity::Taker)),
+                (40000, 0)
+            ),
+            (
+                4,
+                (1001, 1),
+                ExecType::Trade,
+                OrderStatus::PartiallyFilled,
+                (40000, 5000000, Some(Liquidity::Maker)),
+                (40000, 60000)
+            ),
+        ]
+    );
+
+    order_manager
+        .amend_order(1001, 1, 5000000, 50000, &ExecuteAllways, &MarketDataNull)
+        .unwrap();
+    order_manager
+        .cancel_order(1001, 1, &ExecuteAllways, &MarketDataNull)
+        .unwrap();
+    assert_eq!(
+        take_reports(),
+        vec![
+            (
+                5,
+                (1001, 1),
+                ExecType::Replaced,
+                OrderStatus::PartiallyFilled,
+                (0, 0, None),
+                (40000, 50000)
+            ),
+            (
+                6,
+                (1001, 1),
+                ExecType::Cancelled,
+                OrderStatus::Cancelled,
+                (0, 0, None),
+                (40000, 0)
+            ),
+        ]
+    );
+
+    assert!(order_manager
+        .place_order(
+            test_limit_order(&market, 1001, 3, Side::Bid, 0, 100000),
+            &ExecuteAllways,
+            &MarketDataNull,
+        )
+        .is_err());
+    let reports = sink.reports.take();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].exec_type, ExecType::Rejected);
+    assert_eq!(reports[0].order_status, OrderStatus::Rejected);
+    assert_eq!(
+        reports[0].reject_reason,
+        Some(EngineErrorKind::Validation(OrderRejectReason::InvalidPrice))
+    );
+}
+
+#[test]
+fn test_client_order_ids() {
+    use crate::{execution_policy::ExecuteAllways, market_data_policy::MarketDataNull};
+
+    let market = test_market();
+    let (mut order_manager, _) = test_order_manager(&market);
+    let sink = Rc::new(TrackExecutionReports::default());
+    order_manager.set_execution_report_sink(sink.clone());
+    let client_order = |order_id, client_order_id: &str| {
+        Rc::new(Order {
+            client_order_id: Some(client_order_id.into()),
+            ..test_order(
+                &market,
+                1001,
+                order_id,
+                OrderType::Limit(LimitOrder {
+                    side: Side::Bid,
+                    price: 5000000,
+                    quantity: 100000,
+                }),
+            )
+        })
+    };
+    let place_order = |order_manager: &mut OrderManager, order_id, client_order_id| {
+        order_manager
+            .place_order(
+                client_order(order_id, client_order_id),
+                &ExecuteAllways,
+                &MarketDataNull,
+            )
+            .map(|_| ())
+            .map_err(|err| err.kind)
+    };
+
+    let first_id = order_manager.new_order_id();
+    let second_id = order_manager.new_order_id();
+    assert_ne!(first_id, second_id);
+    assert_eq!(place_order(&mut order_manager, first_id, "a"), Ok(()));
+    assert_eq!(
+        place_order(&mut order_manager, second_id, "a"),
+        Err(BookError::DuplicateClientOrderId.into())
+    );
+    assert_eq!(
+        order_manager
+            .get_order_state(1001, second_id)
+            .unwrap()
+            .status,
+        OrderStatus::Rejected
+    );
+
+    // Order with taken id is not reported, so that the open order is not
+    // taken for rejected
+    sink.reports.take();
+    assert_eq!(
+        place_order(&mut order_manager, first_id, "b"),
+        Err(BookError::DuplicateOrder.into())
+    );
+    assert!(sink.reports.borrow().is_empty());
+    assert_eq!(
+        order_manager.get_order_state(1001, "a").unwrap().status,
+        OrderStatus::New
+    );
+
+    // Either id refers to the same order
+    order_manager
+        .amend_order(1001, "a", 5000000, 60000, &ExecuteAllways, &MarketDataNull)
+        .unwrap();
+    assert_eq!(
+        order_manager
+            .get_order_state(1001, first_id)
+            .unwrap()
+            .leaves_quantity,
+        60000
+    );
+    assert_eq!(
+        order_manager.cancel_order(1001, "a", &ExecuteAllways, &MarketDataNull),
+        Ok(60000)
+    );
+    assert_eq!(
+        order_manager.cancel_order(1001, "c", &ExecuteAllways, &MarketDataNull),
+        Err(EngineError::with_ids(
+            1001,
+            0,
+            NotFoundError::ClientOrderId("c".into())
+        ))
+    );
+
+    // Order id and client order id are taken while order is kept in history
+    assert_eq!(
+        place_order(&mut order_manager, first_id, "b"),
+        Err(BookError::DuplicateOrder.into())
+    );
+    let third_id = order_manager.new_order_id();
+    assert_eq!(
+        place_order(&mut order_manager, third_id, "a"),
+        Err(BookError::DuplicateClientOrderId.into())
+    );
+    order_manager.set_history_capacity(0);
+    assert_eq!(place_order(&mut order_manager, third_id, "a"), Ok(()));
+    assert_eq!(place_order(&mut order_manager, first_id, "b"), Ok(()));
+
+    // Participant cannot choose id, which exchange is yet to assign
+    assert_eq!(
+        place_order(&mut order_manager, third_id + 1, "d"),
+        Err(BookError::UnassignedOrderId.into())
+    );
+    assert_ne!(order_manager.new_order_id(), first_id);
+}
+
+#[test]
+fn test_mass_cancel() {
+    use crate::order_book::TrackMarketData;
+
+    let market_btc = test_market();
+    let market_eth = Rc::new(Market {
+        symbol: "ETH/USDT".into(),
+        ..Rc::into_inner(test_market()).unwrap()
+    });
+    let mut order_manager = OrderManager::new(
+        Rc::new(OrderBooks::new(&[
+            Rc::new(RefCell::new(OrderBook::new(market_btc.clone()))),
+            Rc::new(RefCell::new(OrderBook::new(market_eth.clone()))),
+        ])),
+        Rc::new(crate::clock::ManualClock::default()),
+    );
+    let margin_manager = test_margin_manager(&market_btc, &[1001, 1002]);
+    let market_data = TrackMarketData::default();
+
+    for order in [
+        test_limit_order(&market_btc, 1001, 1, Side::Bid, 5000000, 100000),
+        test_limit_order(&market_btc, 1001, 2, Side::Ask, 5100000, 100000),
+        test_limit_order(&market_eth, 1001, 3, Side::Bid, 300000, 200000),
+        test_limit_order(&market_btc, 1002, 4, Side::Bid, 4900000, 100000),
+    ] {
+        order_manager
+            .place_order(order, &margin_manager, &market_data)
+            .unwrap();
+    }
+
+    let summary = order_manager.mass_cancel(
+        &MassCancelFilter {
+            participant_id: Some(1001),
+            side: Some(Side::Bid),
+            ..Default::default()
+        },
+        &margin_manager,
+        &market_data,
+    );
+    assert_eq!(
+        summary.cancelled_orders,
+        vec![((1001, 1), 100000), ((1001, 3), 200000)]
+    );
+    assert!(summary.errors.is_empty());
+    assert_eq!(summary.get_cancelled_quantity(), 300000);
+    assert_eq!(*market_data.cancelled.borrow(), vec![100000, 200000]);
+
+    let summary = order_manager.mass_cancel(
+        &MassCancelFilter {
+            symbol: Some("BTC/USDT".into()),
+            ..Default::default()
+        },
+        &margin_manager,
+        &market_data,
+    );
+    assert_eq!(
+        summary.cancelled_orders,
+        vec![((1001, 2), 100000), ((1002, 4), 100000)]
+    );
+    assert!(order_manager.get_open_orders(1001).is_empty());
+    assert!(order_manager.get_open_orders(1002).is_empty());
+
+    // Promises are released
+    for participant_id in [1001, 1002] {
+        let account = margin_manager.get_participants()[&participant_id].clone();
+        let account = account.borrow();
+        for symbol in ["BTC", "USDT"] {
+            let asset_account = account.portfolio[symbol].borrow();
+            assert_eq!(asset_account.received.quantity_open, 0);
+            assert_eq!(asset_account.delivered.quantity_open, 0);
+        }
+    }
+}
+
+#[test]
+fn test_cancel_disconnected_orders() {
+    use crate::{
+        clock::ManualClock, execution_policy::ExecuteAllways, market_data_policy::MarketDataNull,
+    };
+
+    let market = test_market();
+    let book = Rc::new(RefCell::new(OrderBook::new(market.clone())));
+    let clock = Rc::new(ManualClock::new(1000));
+    let mut order_manager = OrderManager::new(
+        Rc::new(OrderBooks::new(std::slice::from_ref(&book))),
+        clock.clone(),
+    );
+    let order = |participant_id, order_id, cancel_on_disconnect| {
+        Rc::new(Order {
+            cancel_on_disconnect,
+            ..test_order(
+                &market,
+                participant_id,
+                order_id,
+                OrderType::Limit(LimitOrder {
+                    side: Side::Bid,
+                    price: 5000000,
+                    quantity: 100000,
+                }),
+            )
+        })
+    };
+
+    for order in [
+        order(1001, 1, false),
+        order(1002, 2, true),
+        order(1002, 3, false),
+        order(1003, 4, false),
+    ] {
+        order_manager
+            .place_order(order, &ExecuteAllways, &MarketDataNull)
+            .unwrap();
+    }
+    order_manager.register_heartbeat(1001, 1000, CancelOnDisconnect::AllOrders);
+    order_manager.register_heartbeat(1002, 2000, CancelOnDisconnect::FlaggedOrders);
+    assert!(!order_manager.handle_heartbeat(1003));
+
+    clock.set_time(1999);
+    assert!(order_manager.handle_heartbeat(1002));
+    assert_eq!(
+        order_manager.cancel_disconnected_orders(&ExecuteAllways, &MarketDataNull),
+        MassCancelSummary::default()
+    );
+
+    clock.advance(1);
+    assert_eq!(
+        order_manager
+            .cancel_disconnected_orders(&ExecuteAllways, &MarketDataNull)
+            .cancelled_orders,
+        vec![((1001, 1), 100000)]
+    );
+
+    // Disconnected participant is not checked anymore
+    clock.advance(3000);
+    assert_eq!(
+        order_manager
+            .cancel_disconnected_orders(&ExecuteAllways, &MarketDataNull)
+            .cancelled_orders,
+        vec![((1002, 2), 100000)]
+    );
+    assert!(order_manager
+        .cancel_disconnected_orders(&ExecuteAllways, &MarketDataNull)
+        .cancelled_orders
+        .is_empty());
+    assert_eq!(book.borrow().get_order_quantity(1002, 3), Some(100000));
+    assert_eq!(book.borrow().get_order_quantity(1003, 4), Some(100000));
+}
+
+#[test]
+fn test_balance_policy() {
+    use crate::{
+        error::MarginError,
+        margin::{test_deposit, BalancePolicy, MarginManager},
+        market_data_policy::MarketDataNull,
+        order_state::OrderStatus,
+    };
+
+    let market = test_market();
+    let (mut order_manager, book) = test_order_manager(&market);
+    let mut margin_manager = MarginManager::new(crate::margin::MarginLotEventHandlerNull);
+    for (participant_id, asset, quantity) in [
+        (1001, &market.quote_asset, 10000),
+        (1002, &market.base_asset, 10000000),
+    ] {
+        let mut account = margin_manager.add_account(participant_id).borrow_mut();
+        account
+            .add_asset_account(&market.base_asset)
+            .add_asset_account(&market.quote_asset);
+        test_deposit(&mut account, asset, quantity);
+    }
+    let insufficient = |order_id, symbol: &str| {
+        Err(EngineError::with_ids(
+            1001,
+            order_id,
+            MarginError::InsufficientBalance(symbol.into()),
+        ))
+    };
+
+    // Holding 100.00 USDT is enough to bid for 0.002 BTC at 50000.00, but no more
+    for order_id in [1, 2] {
+        order_manager
+            .place_order(
+                test_limit_order(&market, 1001, order_id, Side::Bid, 5000000, 100),
+                &margin_manager,
+                &MarketDataNull,
+            )
+            .unwrap();
+    }
+    assert_eq!(
+        order_manager
+            .place_order(
+                test_limit_order(&market, 1001, 3, Side::Bid, 5000000, 100),
+                &margin_manager,
+                &MarketDataNull,
+            )
+            .map(|_| ()),
+        insufficient(3, "USDT")
+    );
+
+    // Leverage of 3 allows to bid for twice as much on top
+    margin_manager.get_participants()[&1001]
+        .borrow_mut()
+        .set_balance_policy(BalancePolicy::Margin { leverage: 3 });
+    for order_id in [4, 5, 6, 7] {
+        order_manager
+            .place_order(
+                test_limit_order(&market, 1001, order_id, Side::Bid, 5000000, 100),
+                &margin_manager,
+                &MarketDataNull,
+            )
+            .unwrap();
+    }
+    assert_eq!(
+        order_manager
+            .place_order(
+                test_limit_order(&market, 1001, 8, Side::Bid, 5000000, 100),
+                &margin_manager,
+                &MarketDataNull,
+            )
+            .map(|_| ()),
+        insufficient(8, "USDT")
+    );
+
+    // Aggressor cannot pay for executions either
+    order_manager
+        .place_order(
+            test_limit_order(&market, 1002, 9, Side::Ask, 6000000, 60000),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap();
+    order_manager
+        .place_order(
+            test_limit_order(&market, 1001, 10, Side::Bid, 6000000, 60000),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap();
+    let state = order_manager.get_order_state(1001, 10).unwrap();
+    assert_eq!(state.status, OrderStatus::Cancelled);
+    assert_eq!(state.filled_quantity, 0);
+    assert_eq!(book.borrow().get_order_quantity(1002, 9), Some(60000));
+
+    // Quantity promised for ask of 0.6 BTC cannot be withdrawn
+    let withdraw = |quantity| {
+        margin_manager.get_participants()[&1002]
+            .borrow_mut()
+            .transfer(
+                Rc::new(test_order(&market, 1002, 11, OrderType::Withdraw(quantity))),
+                0,
+            )
+    };
+    assert_eq!(
+        withdraw(50000),
+        Err(EngineError::with_ids(
+            1002,
+            11,
+            MarginError::InsufficientBalance("BTC".into())
+        ))
+    );
+    assert_eq!(withdraw(40000), Ok(()));
+    assert_eq!(
+        margin_manager.get_participants()[&1002].borrow().portfolio["BTC"]
+            .borrow()
+            .get_available_balance(),
+        0
+    );
+}
+
+#[test]
+fn test_fill_or_kill_balance() {
+    use crate::{
+        error::MarginError,
+        margin::{test_deposit, MarginManager},
+        market_data_policy::MarketDataNull,
+    };
+
+    let market = test_market();
+    let (mut order_manager, book) = test_order_manager(&market);
+    let mut margin_manager = MarginManager::new(crate::margin::MarginLotEventHandlerNull);
+    for (participant_id, asset, quantity) in [
+        (1001, &market.base_asset, 20000000),
+        (1002, &market.quote_asset, 5000000),
+    ] {
+        let mut account = margin_manager.add_account(participant_id).borrow_mut();
+        account
+            .add_asset_account(&market.base_asset)
+            .add_asset_account(&market.quote_asset);
+        test_deposit(&mut account, asset, quantity);
+    }
+    for (order_id, price) in [(1, 5000000), (2, 5100000)] {
+        order_manager
+            .place_order(
+                test_limit_order(&market, 1001, order_id, Side::Ask, price, 100000),
+                &margin_manager,
+                &MarketDataNull,
+            )
+            .unwrap();
+    }
+    let fill_or_kill_order = |order_id, price, quantity| {
+        Rc::new(test_order(
+            &market,
+            1002,
+            order_id,
+            OrderType::FillOrKill(LimitOrder {
+                side: Side::Bid,
+                price,
+                quantity,
+            }),
+        ))
+    };
+    let get_available_balance = |participant_id, symbol: &str| {
+        margin_manager.get_participants()[&participant_id]
+            .borrow()
+            .portfolio[symbol]
+            .borrow()
+            .get_available_balance()
+    };
+
+    // Holding 50000.00 USDT pays for first execution only, so none is executed
+    assert_eq!(
+        order_manager
+            .place_order(
+                fill_or_kill_order(3, 5100000, 200000),
+                &margin_manager,
+                &MarketDataNull
+            )
+            .map(|_| ()),
+        Err(EngineError::with_ids(
+            1002,
+            3,
+            MarginError::InsufficientBalance("USDT".into())
+        ))
+    );
+    assert_eq!(book.borrow().get_order_quantity(1001, 1), Some(100000));
+    assert_eq!(book.borrow().get_order_quantity(1001, 2), Some(100000));
+    assert_eq!(get_available_balance(1002, "USDT"), 5000000);
+    assert_eq!(get_available_balance(1002, "BTC"), 0);
+    assert_eq!(get_available_balance(1001, "BTC"), 0);
+
+    let outcome = order_manager
+        .place_order(
+            fill_or_kill_order(4, 5000000, 100000),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 100000);
+    assert_eq!(get_available_balance(1002, "USDT"), 0);
+    assert_eq!(get_available_balance(1002, "BTC"), 10000000);
+}
+
+#[test]
+fn test_reduce_only_order() {
+    use crate::{
+        error::MarginError,
+        margin::{test_deposit, MarginManager},
+        market_data_policy::MarketDataNull,
+        order_book::CancelReason,
+    };
+
+    let market = test_market();
+    let (mut order_manager, book) = test_order_manager(&market);
+    let mut margin_manager = MarginManager::new(crate::margin::MarginLotEventHandlerNull);
+    for (participant_id, asset, quantity) in [
+        (1001, &market.base_asset, 100000),
+        (1001, &market.quote_asset, 1000000),
+        (1002, &market.quote_asset, 1000000),
+    ] {
+        let mut account = margin_manager.add_account(participant_id).borrow_mut();
+        account
+            .add_asset_account(&market.base_asset)
+            .add_asset_account(&market.quote_asset);
+        test_deposit(&mut account, asset, quantity);
+    }
+    order_manager
+        .place_order(
+            test_limit_order(&market, 1002, 1, Side::Bid, 5000000, 3000),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap();
+    let reduce_only_order = |order_id, order_data| {
+        Rc::new(Order {
+            reduce_only: true,
+            ..test_order(&market, 1001, order_id, order_data)
+        })
+    };
+    let market_order = |side, quantity| OrderType::Market(MarketOrder { side, quantity });
+
+    // Long 0.01 BTC cannot be added to
+    assert_eq!(
+        order_manager
+            .place_order(
+                reduce_only_order(
+                    3,
+                    OrderType::Limit(LimitOrder {
+                        side: Side::Bid,
+                        price: 4000000,
+                        quantity: 1000,
+                    }),
+                ),
+                &margin_manager,
+                &MarketDataNull,
+            )
+            .map(|_| ()),
+        Err(EngineError::with_ids(
+            1001,
+            3,
+            MarginError::PositionNotReduced
+        ))
+    );
+
+    // Nor turned short by selling 0.02 BTC
+    let outcome = order_manager
+        .place_order(
+            reduce_only_order(4, market_order(Side::Ask, 2000)),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 0);
+    assert_eq!(outcome.cancel_reason, Some(CancelReason::ExecutionRejected));
+    assert_eq!(book.borrow().get_order_quantity(1002, 1), Some(3000));
+
+    // But closed by selling 0.01 BTC
+    let outcome = order_manager
+        .place_order(
+            reduce_only_order(5, market_order(Side::Ask, 1000)),
+            &margin_manager,
+            &MarketDataNull,
+        )
+        .unwrap();
+    assert_eq!(outcome.filled_quantity, 1000);
+    assert_eq!(
+        margin_manager.get_participants()[&1001].borrow().portfolio["BTC"]
+            .borrow()
+            .get_balance(),
+        0
+    );
+}
+
+#[test]
+fn test_initial_margin() {
+    use crate::{
+        error::MarginError,
+        margin::{test_deposit, BalancePolicy, MarginManager, MarginModel, MarginRates},
+        market_data_policy::MarketDataNull,
+        order_state::OrderStatus,
+    };
+
+    let market = test_market();
+    let (mut order_manager, book) = test_order_manager(&market);
+    let mut margin_manager = MarginManager::new(crate::margin::MarginLotEventHandlerNull);
+    for (participant_id, asset, quantity, balance_policy) in [
+        (
+            1001,
+            &market.quote_asset,
+            100000,
+            BalancePolicy::Margin { leverage: 100 },
+        ),
+        (1002, &market.base_asset, 100000000, BalancePolicy::Spot),
+    ] {
+        let mut account = margin_manager.add_account(participant_id).borrow_mut();
+        account
+            .add_asset_account(&market.base_asset)
+            .add_asset_account(&market.quote_asset)
+            .set_balance_policy(balance_policy);
+        test_deposit(&mut account, asset, quantity);
+    }
+    let mut margin_model = MarginModel::new(&market.quote_asset, MarginRates::default());
+    margin_model
+        .set_mark_price("BTC", 5000000)
+        .set_margin_rates(
+            "BTC",
+            MarginRates {
+                initial: 1000,
+                maintenance: 500,
+            },
+        );
+    margin_manager.set_margin_model(margin_model);
+    let mut place_order = |order| {
+        order_manager
+            .place_order(order, &margin_manager, &MarketDataNull)
+            .map(|_| ())
+    };
+
+    // Equity of 1000.00 USDT covers 10% initial margin of 0.2 BTC at 50000.00
+    assert_eq!(
+        place_order(test_limit_order(
+            &market,
+            1001,
+            1,
+            Side::Bid,
+            5000000,
+            30000
+        )),
+        Err(EngineError::with_ids(
+            1001,
+            1,
+            MarginError::InsufficientMargin
+        ))
+    );
+    place_order(test_limit_order(
+        &market,
+        1001,
+        2,
+        Side::Bid,
+        5000000,
+        20000,
+    ))
+    .unwrap();
+    place_order(test_limit_order(
+        &market,
+        1002,
+        3,
+        Side::Ask,
+        5000000,
+        20000,
+    ))
+    .unwrap();
+    assert_eq!(margin_manager.get_margin_ratio(1001), Ok(Some(20000)));
+    assert_eq!(margin_manager.get_margin_ratio(1002), Ok(Some(204081)));
+
+    // Equity of 400.00 USDT is below maintenance margin of 470.00 USDT
+    margin_manager
+        .get_margin_model_mut()
+        .unwrap()
+        .set_mark_price("BTC", 4700000);
+    assert_eq!(margin_manager.get_margin_ratio(1001), Ok(Some(8510)));
+
+    // Aggressor order adding to initial margin is cancelled, but reducing one is not
+    let mut place_order = |order| {
+        order_manager
+            .place_order(order, &margin_manager, &MarketDataNull)
+            .map(|_| ())
+    };
+    place_order(test_limit_order(
+        &market,
+        1002,
+        4,
+        Side::Ask,
+        4700000,
+        10000,
+    ))
+    .unwrap();
+    place_order(test_limit_order(
+        &market,
+        1001,
+        5,
+        Side::Bid,
+        4700000,
+        10000,
+    ))
+    .unwrap();
+    place_order(test_limit_order(
+        &market,
+        1001,
+        6,
+        Side::Ask,
+        4800000,
+        10000,
+    ))
+    .unwrap();
+    assert_eq!(
+        order_manager.get_order_state(1001, 5).unwrap().status,
+        OrderStatus::Cancelled
+    );
+    assert_eq!(book.borrow().get_order_quantity(1002, 4), Some(10000));
+    assert_eq!(book.borrow().get_order_quantity(1001, 6), Some(10000));
+}
+
+#[test]
+fn test_reporting_asset_margin() {
+    use crate::{
+        margin::{test_deposit, BalancePolicy, MarginManager, MarginModel, MarginRates},
+        market_data_policy::MarketDataNull,
+    };
+
+    let market = test_market();
+    let (mut order_manager, _) = test_order_manager(&market);
+    let mut margin_manager = MarginManager::new(crate::margin::MarginLotEventHandlerNull);
+    for (participant_id, asset, quantity) in [
+        (1001, &market.quote_asset, 100000),
+        (1002, &market.base_asset, 100000000),
+    ] {
+        let mut account = margin_manager.add_account(participant_id).borrow_mut();
+        account
+            .add_asset_account(&market.base_asset)
+            .add_asset_account(&market.quote_asset)
+            .set_balance_policy(BalancePolicy::Margin { leverage: 3 });
+        test_deposit(&mut account, asset, quantity);
+    }
+    // Same rates for every asset, reporting asset included
+    let mut margin_model = MarginModel::new(
+        &market.quote_asset,
+        MarginRates {
+            initial: 1000,
+            maintenance: 500,
+        },
+    );
+    margin_model.set_mark_price("BTC", 5000000);
+    margin_manager.set_margin_model(margin_model);
+
+    // Cash of 1000.00 USDT requires no margin
+    let get_margins = |participant_id| {
+        let margin_model = margin_manager.get_margin_model().unwrap();
+        let account = margin_manager.get_participants()[&participant_id].borrow();
+        (
+            account.get_equity(margin_model).unwrap(),
+            account.get_initial_margin(margin_model).unwrap(),
+            account.get_maintenance_margin(margin_model).unwrap(),
+        )
+    };
+    assert_eq!(get_margins(1001), (100000, 0, 0));
+    assert_eq!(margin_manager.get_margin_ratio(1001), Ok(None));
+
+    // Long 0.04 BTC bought for 2000.00 USDT, of which 1000.00 USDT borrowed,
+    // is margined for value of BTC only
+    for (participant_id, order_id, side) in [(1002, 1, Side::Ask), (1001, 2, Side::Bid)] {
+        order_manager
+            .place_order(
+                test_limit_order(&market, participant_id, order_id, side, 5000000, 4000),
+                &margin_manager,
+                &MarketDataNull,
+            )
+            .unwrap();
+    }
+    assert_eq!(
+        margin_manager.get_participants()[&1001].borrow().portfolio["USDT"]
+            .borrow()
+            .get_balance(),
+        -100000
+    );
+    assert_eq!(get_margins(1001), (100000, 20000, 10000));
+    assert_eq!(margin_manager.get_margin_ratio(1001), Ok(Some(100000)));
+}
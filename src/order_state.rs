@@ -0,0 +1,89 @@
+use std::rc::Rc;
+
+use crate::order::Order;
+
+/// Stage of order lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderStatus {
+    /// Accepted, but nothing executed yet
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Rejected,
+    Expired,
+}
+
+impl OrderStatus {
+    /// Tell if nothing can happen to order anymore
+    pub fn is_terminal(&self) -> bool {
+        !matches!(self, Self::New | Self::PartiallyFilled)
+    }
+}
+
+/// Order with its status and cumulative quantities
+#[derive(Clone)]
+pub struct OrderState {
+    pub order: Rc<Order>,
+    pub status: OrderStatus,
+    /// Cumulative executed quantity
+    pub filled_quantity: u64,
+    /// Sum of executed quantity times price
+    pub filled_value: u128,
+    /// Quantity still open for execution
+    pub leaves_quantity: u64,
+}
+
+impl OrderState {
+    pub fn new(order: Rc<Order>) -> Self {
+        Self {
+            leaves_quantity: order.get_quantity().unwrap_or(0),
+            order,
+            status: OrderStatus::New,
+            filled_quantity: 0,
+            filled_value: 0,
+        }
+    }
+
+    pub fn get_key(&self) -> (usize, usize) {
+        (self.order.participant_id, self.order.order_id)
+    }
+
+    /// Volume weighted average price of executions (rounded down)
+    pub fn get_average_price(&self) -> Option<u64> {
+        self.filled_value
+            .checked_div(self.filled_quantity as u128)
+            .and_then(|price| price.try_into().ok())
+    }
+
+    /// Execute part of open quantity
+    ///
+    /// Order sized by value is not filled by execution, as its unspent value
+    /// may yet be cancelled, and it is to be closed instead.
+    pub fn execute(&mut self, quantity: u64, price: u64) {
+        self.filled_quantity += quantity;
+        self.filled_value += quantity as u128 * price as u128;
+        self.leaves_quantity = self.leaves_quantity.saturating_sub(quantity);
+        self.status = if self.leaves_quantity == 0 && self.order.get_quantity().is_some() {
+            OrderStatus::Filled
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+    }
+
+    /// Cancel part of open quantity, or all of it
+    pub fn cancel(&mut self, quantity: u64) {
+        self.leaves_quantity = self.leaves_quantity.saturating_sub(quantity);
+        if self.leaves_quantity == 0 {
+            self.status = OrderStatus::Cancelled;
+        }
+    }
+
+    /// Finish order with terminal status, unless it already is finished
+    pub fn close(&mut self, status: OrderStatus) {
+        if !self.status.is_terminal() {
+            self.status = status;
+            self.leaves_quantity = 0;
+        }
+    }
+}